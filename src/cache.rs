@@ -0,0 +1,227 @@
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_CACHE_DIR: &str = "/var/cache/aoscdk";
+const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Verified system releases kept around for later installs, stored as `<sha256sum>-<file name>`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DownloadCache {
+    pub dir: PathBuf,
+    /// Evict the least recently used releases once the cache grows beyond this many bytes
+    pub size_limit: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct CacheEntry {
+    pub sha256sum: String,
+    pub name: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub last_used: SystemTime,
+}
+
+impl DownloadCache {
+    pub fn new(dir: &Path, size_limit: Option<u64>) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            size_limit,
+        }
+    }
+
+    /// Find a cached release by its checksum, marking it as recently used
+    pub fn lookup(&self, sha256sum: &str) -> Option<PathBuf> {
+        let entry = self
+            .list()
+            .ok()?
+            .into_iter()
+            .find(|x| x.sha256sum == sha256sum)?;
+
+        // eviction goes by modification time
+        if let Err(e) = File::open(&entry.path).and_then(|x| x.set_modified(SystemTime::now())) {
+            warn!(
+                "Failed to update cache entry {}: {}",
+                entry.path.display(),
+                e
+            );
+        }
+
+        Some(entry.path)
+    }
+
+    /// Copy a verified release into the cache, then evict old ones to stay within the size limit
+    pub fn store(&self, file: &Path, sha256sum: &str, url: &str) -> Result<()> {
+        let size = fs::metadata(file)?.len();
+        if self.size_limit.is_some_and(|x| size > x) {
+            info!("{} is larger than the cache size limit, not caching", url);
+            return Ok(());
+        }
+        if self.lookup(sha256sum).is_some() {
+            return Ok(());
+        }
+
+        fs::create_dir_all(&self.dir)?;
+        let file_name = url.rsplit('/').next().unwrap_or(url);
+        let path = self.dir.join(format!("{sha256sum}-{file_name}"));
+        let partial = self
+            .dir
+            .join(format!("{sha256sum}-{file_name}{PARTIAL_SUFFIX}"));
+
+        info!("Caching {} as {}", url, path.display());
+        // copy to a temporary name first, so an interrupted copy is never picked up
+        if let Err(e) = fs::copy(file, &partial).and_then(|_| fs::rename(&partial, &path)) {
+            fs::remove_file(&partial).ok();
+            return Err(e.into());
+        }
+
+        if let Some(size_limit) = self.size_limit {
+            self.evict(size_limit)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn list(&self) -> Result<Vec<CacheEntry>> {
+        let mut entries = vec![];
+        if !self.dir.is_dir() {
+            return Ok(entries);
+        }
+
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.ends_with(PARTIAL_SUFFIX) {
+                continue;
+            }
+            let (sha256sum, file_name) = match name.split_once('-') {
+                Some((sha256sum, file_name))
+                    if sha256sum.len() == 64
+                        && sha256sum.chars().all(|x| x.is_ascii_hexdigit()) =>
+                {
+                    (sha256sum.to_string(), file_name.to_string())
+                }
+                _ => continue,
+            };
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+
+            entries.push(CacheEntry {
+                sha256sum,
+                name: file_name,
+                path: entry.path(),
+                size: metadata.len(),
+                last_used: metadata.modified()?,
+            });
+        }
+        // most recently used first
+        entries.sort_by_key(|x| std::cmp::Reverse(x.last_used));
+
+        Ok(entries)
+    }
+
+    /// Remove the least recently used releases until the cache fits in `size_limit` bytes,
+    /// returns how many bytes were freed
+    pub fn evict(&self, size_limit: u64) -> Result<u64> {
+        let mut total = 0;
+        let mut freed = 0;
+        for entry in self.list()? {
+            total += entry.size;
+            if total <= size_limit {
+                continue;
+            }
+
+            info!("Removing {} from cache", entry.path.display());
+            fs::remove_file(&entry.path)
+                .map_err(|e| anyhow!("Failed to remove {}: {}", entry.path.display(), e))?;
+            freed += entry.size;
+        }
+
+        Ok(freed)
+    }
+
+    /// Remove every cached release, including leftovers of interrupted copies
+    pub fn clean(&self) -> Result<u64> {
+        let mut freed = self.evict(0)?;
+        if !self.dir.is_dir() {
+            return Ok(freed);
+        }
+
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if entry
+                .file_name()
+                .to_string_lossy()
+                .ends_with(PARTIAL_SUFFIX)
+            {
+                freed += entry.metadata()?.len();
+                fs::remove_file(entry.path())?;
+            }
+        }
+
+        Ok(freed)
+    }
+}
+
+#[test]
+fn test_download_cache() {
+    use std::time::Duration;
+
+    let dir = tempfile::tempdir().unwrap();
+    let cache = DownloadCache::new(&dir.path().join("cache"), None);
+    let sha256sum = "a".repeat(64);
+    let release = dir.path().join("release");
+    fs::write(&release, b"12345678").unwrap();
+
+    assert!(cache.lookup(&sha256sum).is_none());
+    cache
+        .store(&release, &sha256sum, "os-x86_64/base/base.squashfs")
+        .unwrap();
+    let cached = cache.lookup(&sha256sum).unwrap();
+    assert!(cached.to_string_lossy().ends_with("-base.squashfs"));
+
+    let other = "b".repeat(64);
+    cache.store(&release, &other, "base.tar.xz").unwrap();
+    let set_last_used = |sha256sum: &str, ago: u64| {
+        let entry = cache
+            .list()
+            .unwrap()
+            .into_iter()
+            .find(|x| x.sha256sum == sha256sum)
+            .unwrap();
+        File::options()
+            .write(true)
+            .open(entry.path)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(ago))
+            .unwrap();
+    };
+    set_last_used(&sha256sum, 7200);
+    set_last_used(&other, 3600);
+    let entries = cache.list().unwrap();
+    assert_eq!(entries[0].sha256sum, other);
+    assert_eq!(entries[1].sha256sum, sha256sum);
+
+    // a lookup makes the older release the most recently used one, so the other is evicted
+    cache.lookup(&sha256sum).unwrap();
+    assert_eq!(cache.evict(10).unwrap(), 8);
+    let entries = cache.list().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].sha256sum, sha256sum);
+
+    // storing goes over the limit and evicts down to it
+    let limited = DownloadCache::new(&cache.dir, Some(10));
+    limited.store(&release, &other, "base.tar.xz").unwrap();
+    assert_eq!(limited.list().unwrap().len(), 1);
+
+    assert_eq!(cache.clean().unwrap(), 8);
+    assert!(cache.list().unwrap().is_empty());
+}
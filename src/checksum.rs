@@ -0,0 +1,98 @@
+use std::sync::mpsc;
+
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc as async_mpsc;
+
+/// Create a hashing pipeline with a fixed pool of `buffers` buffers of `buffer_size` bytes.
+///
+/// The writer blocks (or waits) whenever all buffers are queued for hashing, so memory use
+/// stays the same however fast the data arrives compared to how fast it can be hashed.
+pub fn pipeline(buffers: usize, buffer_size: usize) -> (ChecksumWriter, ChecksumWorker) {
+    let buffers = buffers.max(1);
+    let (full_tx, full_rx) = mpsc::channel();
+    let (free_tx, free_rx) = async_mpsc::channel(buffers);
+    for _ in 0..buffers {
+        free_tx.try_send(Vec::with_capacity(buffer_size)).ok();
+    }
+
+    (
+        ChecksumWriter {
+            full_tx,
+            free_rx,
+            buffer_size: buffer_size.max(1),
+        },
+        ChecksumWorker { full_rx, free_tx },
+    )
+}
+
+/// Feeds data to a `ChecksumWorker`, dropping it marks the end of the data
+pub struct ChecksumWriter {
+    full_tx: mpsc::Sender<Vec<u8>>,
+    free_rx: async_mpsc::Receiver<Vec<u8>>,
+    buffer_size: usize,
+}
+
+impl ChecksumWriter {
+    /// Queue `data` for hashing, waiting for free buffers as needed
+    pub async fn write(&mut self, data: &[u8]) -> Result<()> {
+        for part in data.chunks(self.buffer_size) {
+            let buf = self.free_rx.recv().await;
+            self.submit(buf, part)?;
+        }
+
+        Ok(())
+    }
+
+    /// Like `write`, for use outside of the async runtime
+    pub fn blocking_write(&mut self, data: &[u8]) -> Result<()> {
+        for part in data.chunks(self.buffer_size) {
+            let buf = self.free_rx.blocking_recv();
+            self.submit(buf, part)?;
+        }
+
+        Ok(())
+    }
+
+    fn submit(&self, buf: Option<Vec<u8>>, part: &[u8]) -> Result<()> {
+        let mut buf = buf.ok_or_else(|| anyhow!("The checksum worker has stopped."))?;
+        buf.clear();
+        buf.extend_from_slice(part);
+        self.full_tx
+            .send(buf)
+            .map_err(|_| anyhow!("The checksum worker has stopped."))
+    }
+}
+
+/// Hashes what is written to the `ChecksumWriter`, usually on its own thread
+pub struct ChecksumWorker {
+    full_rx: mpsc::Receiver<Vec<u8>>,
+    free_tx: async_mpsc::Sender<Vec<u8>>,
+}
+
+impl ChecksumWorker {
+    /// Hash until the writer is dropped, returns the SHA-256 checksum of everything written
+    pub fn run(self) -> String {
+        let mut hasher = Sha256::new();
+        for buf in self.full_rx.iter() {
+            hasher.update(&buf);
+            // hand the buffer back to the writer, unless it is gone already
+            self.free_tx.try_send(buf).ok();
+        }
+
+        hex::encode(hasher.finalize())
+    }
+}
+
+#[test]
+fn test_checksum_pipeline() {
+    let (mut writer, worker) = pipeline(2, 4);
+    let worker = std::thread::spawn(move || worker.run());
+    for _ in 0..100 {
+        writer.blocking_write(b"hello ").unwrap();
+    }
+    drop(writer);
+
+    let expected = hex::encode(Sha256::digest("hello ".repeat(100)));
+    assert_eq!(worker.join().unwrap(), expected);
+}
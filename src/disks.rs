@@ -18,17 +18,37 @@ use libparted_sys::PedPartitionFlag;
 use libparted_sys::PedPartitionType;
 use log::error;
 use log::info;
+use rustix::mount;
 use serde::{Deserialize, Serialize};
 use std::ffi::CStr;
+use std::ffi::OsStr;
 use std::ffi::OsString;
 use std::io;
+use std::io::Write;
+use std::path::Component;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
+use std::process::Stdio;
+use std::sync::Mutex;
 
 const EFI_DETECT_PATH: &str = "/sys/firmware/efi";
-pub const ALLOWED_FS_TYPE: &[&str] = &["ext4", "xfs"];
+pub const ALLOWED_FS_TYPE: &[&str] = &["ext4", "xfs", "btrfs"];
 const DEFAULT_FS_TYPE: &str = "ext4";
+/// Subvolumes created on Btrfs root filesystems, and where they are mounted
+pub const BTRFS_SUBVOLUMES: &[(&str, &str)] = &[("@", "/"), ("@home", "/home")];
+const BTRFS_MOUNT_OPTIONS: &str = "compress=zstd:1";
+/// Device mapper name of the encrypted system partition while installing
+pub const LUKS_MAPPER_NAME: &str = "dkroot";
+// space taken by the LUKS2 header at the start of the partition
+const LUKS2_HEADER_SIZE: u64 = 16 * 1024 * 1024;
+/// Volume group created on the system partition for LVM layouts
+pub const LVM_VG_NAME: &str = "aosc";
+/// Volume group created by `create_lvm` in this run, the only one `lvm_close` deactivates
+static CREATED_VG: Mutex<Option<String>> = Mutex::new(None);
+
+// mounted by the installer itself, or by the system at boot
+const RESERVED_MOUNT_POINTS: &[&str] = &["/efi", "/dev", "/proc", "/sys", "/run"];
 
 const SUPPORT_PARTITION_TYPE: &[&str] = &["primary", "logical"];
 
@@ -40,6 +60,47 @@ pub struct Partition {
     pub size: u64,
 }
 
+/// An extra partition mounted into the installed system, e.g. at /home or /var
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MountPoint {
+    /// `fs_type` is the filesystem to format it as, or its current one if it is kept
+    pub partition: Partition,
+    pub mount_path: PathBuf,
+    pub format: bool,
+}
+
+/// Logical volumes to create in `LVM_VG_NAME`, a swap volume is added if swap is enabled
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LvmLayout {
+    /// Size of the root volume, the rest goes to a home volume.
+    /// Without it, the root volume takes the whole volume group.
+    pub root_size: Option<u64>,
+}
+
+/// Logical volumes created by `create_lvm`
+pub struct LvmVolumes {
+    pub root: Partition,
+    pub home: Option<Partition>,
+    pub swap: Option<Partition>,
+}
+
+#[derive(Deserialize)]
+struct LvsReport {
+    report: Vec<LvsReportEntry>,
+}
+
+#[derive(Deserialize)]
+struct LvsReportEntry {
+    lv: Vec<LvsEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LvsEntry {
+    lv_path: String,
+    lv_size: String,
+    devices: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DkDerive {
     pub path: PathBuf,
@@ -65,7 +126,11 @@ pub fn get_recommended_fs_type(type_: &str) -> &str {
 pub fn format_partition(partition: &Partition) -> Result<()> {
     let default_fs = DEFAULT_FS_TYPE.to_owned();
     let fs_type = partition.fs_type.as_ref().unwrap_or(&default_fs);
-    let mut command = Command::new(format!("mkfs.{fs_type}"));
+    let mut command = if fs_type == "swap" {
+        Command::new("mkswap")
+    } else {
+        Command::new(format!("mkfs.{fs_type}"))
+    };
     let cmd;
 
     if fs_type == "ext4" {
@@ -96,6 +161,499 @@ pub fn format_partition(partition: &Partition) -> Result<()> {
     Ok(())
 }
 
+/// Create the subvolumes in `BTRFS_SUBVOLUMES` on a freshly formatted Btrfs root partition
+pub fn create_btrfs_subvolumes(device_path: &Path) -> Result<()> {
+    let top_level = tempfile::Builder::new().prefix(".dkbtrfs").tempdir()?;
+    mount::mount(
+        device_path,
+        top_level.path(),
+        "btrfs",
+        mount::MountFlags::empty(),
+        "",
+    )?;
+
+    let result = BTRFS_SUBVOLUMES.iter().try_for_each(|(subvolume, _)| {
+        let output = Command::new("btrfs")
+            .arg("subvolume")
+            .arg("create")
+            .arg(top_level.path().join(subvolume))
+            .output()?;
+        if !output.status.success() {
+            bail!(
+                "Installer failed to create Btrfs subvolume {}: \n{}",
+                subvolume,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        Ok(())
+    });
+    mount::unmount(top_level.path(), mount::UnmountFlags::empty())?;
+
+    result
+}
+
+/// Mount options of the given Btrfs subvolume, for mounting and for fstab
+pub fn btrfs_mount_options(subvolume: &str) -> String {
+    format!("subvol={subvolume},{BTRFS_MOUNT_OPTIONS}")
+}
+
+/// Run a storage tool such as cryptsetup or lvcreate, returns its trimmed stdout
+///
+/// `input` is written to its stdin, e.g. as the key file of cryptsetup.
+fn run_storage_command<I, S>(program: &str, args: I, input: Option<&str>) -> Result<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut command = Command::new(program);
+    command.args(args);
+    info!("{command:?}");
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(input) = input {
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow!("Installer could not write to the stdin of {program}."))?;
+        stdin.write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!(
+            "Installer failed to run {}: \n{}",
+            program,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn partition_device(partition: &Partition) -> Result<&Path> {
+    partition.path.as_deref().ok_or_else(|| {
+        anyhow!("Installer could not find the specified partition.\nDid you partition your target disk?")
+    })
+}
+
+/// Turn the partition into a LUKS2 container protected by `passphrase`, erasing it
+pub fn luks_format(partition: &Partition, passphrase: &str) -> Result<()> {
+    let device = partition_device(partition)?;
+    // GRUB reads /boot from the container, and it can not unlock argon2 keyslots
+    // the whole key file is the key, so the passphrase goes without a trailing newline
+    run_storage_command(
+        "cryptsetup",
+        [
+            OsStr::new("luksFormat"),
+            OsStr::new("--batch-mode"),
+            OsStr::new("--type=luks2"),
+            OsStr::new("--pbkdf=pbkdf2"),
+            OsStr::new("--key-file=-"),
+            device.as_os_str(),
+        ],
+        Some(passphrase),
+    )?;
+
+    Ok(())
+}
+
+/// UUID of the LUKS container, as used in crypttab and by `rd.luks.uuid`
+pub fn luks_uuid(partition: &Partition) -> Result<String> {
+    let device = partition_device(partition)?;
+
+    run_storage_command(
+        "cryptsetup",
+        [OsStr::new("luksUUID"), device.as_os_str()],
+        None,
+    )
+}
+
+/// Open the LUKS container as `LUKS_MAPPER_NAME`, returns the mapped partition to install onto
+pub fn luks_open(partition: &Partition, passphrase: &str) -> Result<Partition> {
+    let device = partition_device(partition)?;
+    run_storage_command(
+        "cryptsetup",
+        [
+            OsStr::new("open"),
+            OsStr::new("--type=luks2"),
+            OsStr::new("--key-file=-"),
+            device.as_os_str(),
+            OsStr::new(LUKS_MAPPER_NAME),
+        ],
+        Some(passphrase),
+    )?;
+
+    Ok(Partition {
+        path: Some(Path::new("/dev/mapper").join(LUKS_MAPPER_NAME)),
+        parent_path: partition.parent_path.clone(),
+        fs_type: partition.fs_type.clone(),
+        size: partition.size.saturating_sub(LUKS2_HEADER_SIZE),
+    })
+}
+
+/// Close the mapping opened by `luks_open`, once nothing uses it anymore
+pub fn luks_close() {
+    if Path::new("/dev/mapper").join(LUKS_MAPPER_NAME).exists() {
+        run_storage_command(
+            "cryptsetup",
+            ["close", "--deferred", LUKS_MAPPER_NAME],
+            None,
+        )
+        .ok();
+    }
+}
+
+/// Set up `LVM_VG_NAME` on the partition and create the logical volumes of `layout` in it,
+/// with a swap volume of `swap_size` bytes if given
+pub fn create_lvm(
+    partition: &Partition,
+    layout: &LvmLayout,
+    swap_size: Option<u64>,
+) -> Result<LvmVolumes> {
+    if layout.root_size.is_some() && partition.fs_type.as_deref() == Some("btrfs") {
+        bail!("Btrfs keeps /home in its own subvolume, a separate home logical volume is not supported.");
+    }
+    let device = partition_device(partition)?;
+    // the partition may still hold the volume group of an earlier installation
+    if let Some(vg) = pv_volume_group(device) {
+        let pvs = vg_physical_volumes(&vg)?;
+        if pvs.iter().any(|x| !is_same_device(x, device)) {
+            bail!(
+                "The partition {} belongs to the volume group {}, which also spans other partitions.\n\nPlease remove the volume group before installing AOSC OS onto this partition.",
+                device.display(),
+                vg
+            );
+        }
+        remove_volume_group(&vg, device)?;
+    }
+    if vg_physical_volumes(LVM_VG_NAME).is_ok() {
+        bail!(
+            "A volume group named {} already exists on another partition.\n\nPlease rename or remove it before installing AOSC OS with LVM.",
+            LVM_VG_NAME
+        );
+    }
+    run_storage_command(
+        "pvcreate",
+        [
+            OsStr::new("--yes"),
+            OsStr::new("--force"),
+            device.as_os_str(),
+        ],
+        None,
+    )?;
+    run_storage_command(
+        "vgcreate",
+        [OsStr::new(LVM_VG_NAME), device.as_os_str()],
+        None,
+    )?;
+    *CREATED_VG.lock().unwrap() = Some(LVM_VG_NAME.to_string());
+
+    // fixed sizes first, the last volume takes what is left
+    let swap = swap_size
+        .map(|size| create_logical_volume(partition, "swap", Some(size), "swap"))
+        .transpose()?;
+    let fs_type = partition.fs_type.as_deref().unwrap_or(DEFAULT_FS_TYPE);
+    let root = create_logical_volume(partition, "root", layout.root_size, fs_type)?;
+    let home = layout
+        .root_size
+        .map(|_| create_logical_volume(partition, "home", None, fs_type))
+        .transpose()?;
+
+    Ok(LvmVolumes { root, home, swap })
+}
+
+/// Volume group the physical volume on `device` belongs to, or None if it is not in one
+fn pv_volume_group(device: &Path) -> Option<String> {
+    run_storage_command(
+        "pvs",
+        [
+            OsStr::new("--noheadings"),
+            OsStr::new("--options=vg_name"),
+            device.as_os_str(),
+        ],
+        None,
+    )
+    .ok()
+    .filter(|x| !x.is_empty())
+}
+
+/// Physical volumes of the volume group `vg`, fails if there is no such volume group
+fn vg_physical_volumes(vg: &str) -> Result<Vec<PathBuf>> {
+    let pvs = run_storage_command("vgs", ["--noheadings", "--options=pv_name", vg], None)?;
+
+    Ok(pvs.lines().map(|x| PathBuf::from(x.trim())).collect())
+}
+
+fn is_same_device(a: &Path, b: &Path) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Remove the volume group `vg` and wipe the physical volume on `device`
+fn remove_volume_group(vg: &str, device: &Path) -> Result<()> {
+    info!("Removing volume group {} from {}", vg, device.display());
+    run_storage_command("vgchange", ["--activate=n", vg], None).ok();
+    run_storage_command("vgremove", ["--yes", "--force", vg], None)
+        .and_then(|_| {
+            // a plain --force refuses to wipe a physical volume that was in a volume group
+            run_storage_command(
+                "pvremove",
+                [
+                    OsStr::new("--yes"),
+                    OsStr::new("--force"),
+                    OsStr::new("--force"),
+                    device.as_os_str(),
+                ],
+                None,
+            )
+        })
+        .map_err(|e| {
+            anyhow!(
+                "Installer could not remove the volume group {} on {}, please make sure none of its logical volumes are in use.\n\n{}",
+                vg,
+                device.display(),
+                e
+            )
+        })?;
+
+    Ok(())
+}
+
+/// Create a logical volume in `LVM_VG_NAME` on `pv`, taking the rest of the volume group without `size`
+fn create_logical_volume(
+    pv: &Partition,
+    name: &str,
+    size: Option<u64>,
+    fs_type: &str,
+) -> Result<Partition> {
+    let size_arg = match size {
+        Some(size) => format!("--size={size}b"),
+        None => "--extents=100%FREE".to_string(),
+    };
+    let name_arg = format!("--name={name}");
+    run_storage_command(
+        "lvcreate",
+        [
+            "--yes",
+            "--wipesignatures=y",
+            size_arg.as_str(),
+            name_arg.as_str(),
+            LVM_VG_NAME,
+        ],
+        None,
+    )?;
+
+    let path = Path::new("/dev").join(LVM_VG_NAME).join(name);
+    let size = run_storage_command(
+        "lvs",
+        [
+            OsStr::new("--noheadings"),
+            OsStr::new("--units=b"),
+            OsStr::new("--nosuffix"),
+            OsStr::new("--options=lv_size"),
+            path.as_os_str(),
+        ],
+        None,
+    )?
+    .parse()?;
+
+    Ok(Partition {
+        path: Some(path),
+        parent_path: pv.parent_path.clone(),
+        fs_type: Some(fs_type.to_string()),
+        size,
+    })
+}
+
+/// `vg/lv` name of the logical volume, or None if the partition is not one
+pub fn lvm_volume_name(partition: &Partition) -> Option<String> {
+    let device = partition.path.as_ref()?;
+    let output = run_storage_command(
+        "lvs",
+        [
+            OsStr::new("--noheadings"),
+            OsStr::new("--options=vg_name,lv_name"),
+            device.as_os_str(),
+        ],
+        None,
+    )
+    .ok()?;
+    let mut names = output.split_whitespace();
+
+    Some(format!("{}/{}", names.next()?, names.next()?))
+}
+
+/// Deactivate the volume group created by `create_lvm`, so its partition can be closed
+///
+/// Volume groups that were there before, e.g. holding the logical volume to install to, are left alone.
+pub fn lvm_close() {
+    if let Some(vg) = CREATED_VG.lock().unwrap().take() {
+        run_storage_command("vgchange", ["--activate=n", vg.as_str()], None).ok();
+    }
+}
+
+fn parse_lvs(data: &str) -> Result<Vec<LvsEntry>> {
+    let report: LvsReport = serde_json::from_str(data)?;
+
+    Ok(report
+        .report
+        .into_iter()
+        .flat_map(|x| x.lv)
+        // thin pools and other internal volumes have no device file
+        .filter(|x| !x.lv_path.is_empty())
+        .collect())
+}
+
+/// Disk holding the block device, following device mapper devices down to their partitions
+fn parent_disk(device: &Path) -> Option<PathBuf> {
+    let name = std::fs::canonicalize(device).ok()?.file_name()?.to_owned();
+    let sys_path = Path::new("/sys/class/block").join(&name);
+    if sys_path.join("partition").exists() {
+        let disk = std::fs::canonicalize(&sys_path)
+            .ok()?
+            .parent()?
+            .file_name()?
+            .to_owned();
+        return Some(Path::new("/dev").join(disk));
+    }
+    if let Some(Ok(slave)) = std::fs::read_dir(sys_path.join("slaves"))
+        .ok()
+        .and_then(|mut x| x.next())
+    {
+        return parent_disk(&Path::new("/dev").join(slave.file_name()));
+    }
+
+    Some(Path::new("/dev").join(name))
+}
+
+/// All logical volumes on the system, `parent_path` is the disk holding their (first) physical volume
+pub fn list_logical_volumes() -> Vec<Partition> {
+    let lvs = run_storage_command(
+        "lvs",
+        [
+            "--reportformat=json",
+            "--units=b",
+            "--nosuffix",
+            "--options=lv_path,lv_size,devices",
+        ],
+        None,
+    )
+    .and_then(|x| parse_lvs(&x));
+    let lvs = match lvs {
+        Ok(lvs) => lvs,
+        Err(e) => {
+            info!("Installer could not list logical volumes: {}", e);
+            return vec![];
+        }
+    };
+
+    lvs.into_iter()
+        .map(|lv| {
+            // e.g. /dev/sda2(0),/dev/sdb1(0)
+            let pv = lv.devices.split(['(', ',']).next().unwrap_or_default();
+            let fs_type = run_storage_command(
+                "blkid",
+                ["-o", "value", "-s", "TYPE", lv.lv_path.as_str()],
+                None,
+            )
+            .ok()
+            .filter(|x| !x.is_empty());
+
+            Partition {
+                parent_path: parent_disk(Path::new(pv)),
+                path: Some(PathBuf::from(lv.lv_path)),
+                fs_type,
+                size: lv.lv_size.parse().unwrap_or(0),
+            }
+        })
+        .collect()
+}
+
+/// Check that the mount points can be used next to the `root` partition and its `lvm` layout
+pub fn check_mount_points(
+    root: &Partition,
+    lvm: Option<&LvmLayout>,
+    mount_points: &[MountPoint],
+) -> Result<()> {
+    // /home has its own subvolume or logical volume already
+    let has_home =
+        root.fs_type.as_deref() == Some("btrfs") || lvm.is_some_and(|x| x.root_size.is_some());
+    for (i, mount_point) in mount_points.iter().enumerate() {
+        let mount_path = &mount_point.mount_path;
+        let partition = &mount_point.partition;
+        let device = partition_device(partition)?;
+        if !mount_path.is_absolute()
+            || mount_path == Path::new("/")
+            || mount_path
+                .components()
+                .any(|x| matches!(x, Component::CurDir | Component::ParentDir))
+        {
+            bail!(
+                "Mount point {} must be a plain absolute path other than /.",
+                mount_path.display()
+            );
+        }
+        if RESERVED_MOUNT_POINTS
+            .iter()
+            .any(|x| mount_path.starts_with(x))
+            || (has_home && mount_path.starts_with("/home"))
+        {
+            bail!(
+                "{} is already taken care of by the installer.",
+                mount_path.display()
+            );
+        }
+        let earlier = &mount_points[..i];
+        if earlier.iter().any(|x| &x.mount_path == mount_path) {
+            bail!(
+                "More than one partition is assigned to {}.",
+                mount_path.display()
+            );
+        }
+        if root.path.as_deref() == Some(device)
+            || earlier
+                .iter()
+                .any(|x| x.partition.path.as_deref() == Some(device))
+        {
+            bail!("Partition {} is used more than once.", device.display());
+        }
+        match partition.fs_type.as_deref() {
+            Some(fs_type) if mount_point.format && !ALLOWED_FS_TYPE.contains(&fs_type) => bail!(
+                "Unsupported filesystem type: {}, supported types are: {}",
+                fs_type,
+                ALLOWED_FS_TYPE.join(", ")
+            ),
+            None => bail!(
+                "Partition {} has no filesystem, it needs to be formatted.",
+                device.display()
+            ),
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Format the partition as `fs_type` instead of its current filesystem
+pub fn set_fs_type(part: &Partition, fs_type: &str) -> Result<Partition> {
+    if !ALLOWED_FS_TYPE.contains(&fs_type) {
+        bail!(
+            "Unsupported filesystem type: {}, supported types are: {}",
+            fs_type,
+            ALLOWED_FS_TYPE.join(", ")
+        );
+    }
+    let mut new_part = part.clone();
+    new_part.fs_type = Some(fs_type.to_string());
+
+    Ok(new_part)
+}
+
 pub fn fill_fs_type(part: &Partition, use_ext4: bool) -> Partition {
     let mut new_part = part.clone();
     let new_fs_type: String;
@@ -179,8 +737,8 @@ fn device_is_match(path: &Path, pattern: &str) -> bool {
         .and_then(|x| {
             path.display()
                 .to_string()
-                .split('/')
-                .last()
+                .rsplit('/')
+                .next()
                 .and_then(|dev| x.is_match(dev).ok())
         })
         .unwrap_or(false)
@@ -309,10 +867,12 @@ pub fn right_combine(device_path: Option<&Path>) -> Result<()> {
     Ok(())
 }
 
+/// `options` replaces the default mount options of `fs_type`
 pub fn fstab_entries(
     device_path: Option<&PathBuf>,
     fs_type: &str,
     mount_path: Option<&Path>,
+    options: Option<&str>,
 ) -> Result<OsString> {
     let target = device_path.ok_or_else(|| {
         anyhow!(
@@ -334,7 +894,7 @@ pub fn fstab_entries(
             target.display()
         )
     })?;
-    let root = BlockInfo::new(root_id, fs_type, mount_path, option);
+    let root = BlockInfo::new(root_id, fs_type, mount_path, options.unwrap_or(option));
     let fstab = &mut OsString::new();
     root.write_entry(fstab);
 
@@ -386,16 +946,7 @@ pub fn auto_create_partitions(dev: &Path) -> Result<Partition> {
     let sector_size = device.sector_size();
     let size = length * sector_size;
 
-    if get_partition_table_type(Some(dev))
-        .map(|x| x == "msdos")
-        .unwrap_or(false)
-        && size > 512 * (2_u64.pow(31) - 1)
-    {
-        bail!(
-            r#"AOSC OS Installer has detected that you are trying to create a disk partition larger than 2TiB in the MBR partition table.
-If you want to do this, change your computer's boot mode to UEFI mode."#
-        );
-    }
+    check_mbr_size(dev, size)?;
 
     let disk = libparted::Disk::new(&mut *device)?;
     let mut nums = vec![];
@@ -527,16 +1078,7 @@ pub fn auto_create_partitions(dev: &Path) -> Result<Partition> {
 
     let size = device.length() * sector_size;
 
-    if get_partition_table_type(Some(dev))
-        .map(|x| x == "msdos")
-        .unwrap_or(false)
-        && size > 512 * (2_u64.pow(31) - 1)
-    {
-        bail!(
-            r#"AOSC OS Installer has detected that you are trying to create a disk partition larger than 2TiB in the MBR partition table.
-If you want to do this, change your computer's boot mode to UEFI mode."#
-        );
-    }
+    check_mbr_size(dev, size)?;
 
     if let Ok(disk) = libparted::Disk::new(&mut *device) {
         info!("Disk already exists, open disk and remove existing partitions");
@@ -701,6 +1243,230 @@ If you want to do this, change your computer's boot mode to UEFI mode."#
     Ok(p)
 }
 
+/// MBR partition tables can not address partitions reaching beyond `size` bytes on large drives
+fn check_mbr_size(dev: &Path, size: u64) -> Result<()> {
+    if get_partition_table_type(Some(dev))
+        .map(|x| x == "msdos")
+        .unwrap_or(false)
+        && size > 512 * (2_u64.pow(31) - 1)
+    {
+        bail!(
+            r#"AOSC OS Installer has detected that you are trying to create a disk partition larger than 2TiB in the MBR partition table.
+If you want to do this, change your computer's boot mode to UEFI mode."#
+        );
+    }
+
+    Ok(())
+}
+
+/// Align the free region of `length` sectors at `start` to MiB boundaries,
+/// returns its first sector and the sector after it, or None if nothing is left
+fn align_free_region(start: u64, length: u64, sector_size: u64) -> Option<(u64, u64)> {
+    let align = (1024 * 1024 / sector_size).max(1);
+    let aligned_start = start.div_ceil(align) * align;
+    let aligned_end = (start + length) / align * align;
+
+    (aligned_end > aligned_start).then_some((aligned_start, aligned_end))
+}
+
+/// Which kind of partition fits the free region `start..end`: a logical one inside the
+/// `extended` partition, a primary one while `needed` more primary partitions are allowed
+/// next to the `primaries` already there, or none
+fn free_region_kind(
+    (start, end): (u64, u64),
+    extended: Option<(u64, u64)>,
+    primaries: u32,
+    max_primaries: u32,
+    needed: u32,
+) -> Option<PartitionType> {
+    if extended.is_some_and(|(ext_start, ext_end)| start >= ext_start && end <= ext_end) {
+        Some(PartitionType::Logical)
+    } else if primaries + needed <= max_primaries {
+        Some(PartitionType::Primary)
+    } else {
+        None
+    }
+}
+
+/// Where `create_partition_in_free_space` puts the system partition
+struct FreeSpacePlan {
+    start_sector: u64,
+    end_sector: u64,
+    sector_size: u64,
+    kind: PartitionType,
+    needs_esp: bool,
+}
+
+impl FreeSpacePlan {
+    fn esp_size(&self) -> u64 {
+        512 * 1024 * 1024 / self.sector_size
+    }
+
+    /// First sector of the system partition, after the new ESP if there is one
+    fn system_start(&self) -> u64 {
+        self.start_sector + if self.needs_esp { self.esp_size() } else { 0 }
+    }
+
+    fn system_size(&self) -> u64 {
+        (self.end_sector - self.system_start()) * self.sector_size
+    }
+}
+
+/// Find the largest unallocated region of `dev` usable for a system partition of `min_size`
+/// bytes, without touching the drive
+fn plan_free_space(dev: &Path, min_size: u64) -> Result<FreeSpacePlan> {
+    let mut device = libparted::Device::new(dev)?;
+    let sector_size = device.sector_size();
+    let needs_esp = is_efi_booted() && find_esp_partition(dev).is_err();
+    let disk = libparted::Disk::new(&mut device)?;
+    let extended = disk.extended_partition().and_then(|x| {
+        Some((
+            x.geom_start().try_into().ok()?,
+            u64::try_from(x.geom_end()).ok()? + 1,
+        ))
+    });
+    let primaries = disk.get_primary_partition_count();
+    let max_primaries = disk.get_max_primary_partition_count();
+    let needed = if needs_esp { 2 } else { 1 };
+
+    let mut has_free = false;
+    let region = disk
+        .parts()
+        .filter(|x| x.type_get_name() == "free")
+        .filter_map(|x| {
+            let region = align_free_region(
+                x.geom_start().try_into().ok()?,
+                x.geom_length().try_into().ok()?,
+                sector_size,
+            )?;
+            has_free = true;
+            let kind = free_region_kind(region, extended, primaries, max_primaries, needed)?;
+
+            Some((region, kind))
+        })
+        // an ESP in a logical partition is not found by the firmware
+        .filter(|(_, kind)| !needs_esp || *kind == PartitionType::Primary)
+        .max_by_key(|((start, end), _)| end - start);
+    drop(disk);
+    let ((start_sector, end_sector), kind) = match region {
+        Some(region) => region,
+        None if has_free => bail!(
+            "Installer could not create a partition in the unallocated space on {}, as the partition table has no room for another primary partition.",
+            dev.display()
+        ),
+        None => bail!(
+            "Installer could not find any unallocated space on {}.",
+            dev.display()
+        ),
+    };
+    info!(
+        "Largest usable unallocated region: {} - {} ({:?})",
+        start_sector, end_sector, kind
+    );
+    check_mbr_size(dev, end_sector * sector_size)?;
+
+    let plan = FreeSpacePlan {
+        start_sector,
+        end_sector,
+        sector_size,
+        kind,
+        needs_esp,
+    };
+    let available = if plan.system_start() < end_sector {
+        plan.system_size()
+    } else {
+        0
+    };
+    if available < min_size {
+        bail!(
+            "The unallocated space on {} is not large enough to install AOSC OS release!\n\nAvailable space: {:.3}GiB\nRequired space: {:.3}GiB",
+            dev.display(),
+            available as f32 / 1024.0 / 1024.0 / 1024.0,
+            min_size as f32 / 1024.0 / 1024.0 / 1024.0
+        );
+    }
+
+    Ok(plan)
+}
+
+/// Check that `create_partition_in_free_space` can create a system partition of `min_size`
+/// bytes on `dev`, returns the partition it would create (without a path yet)
+pub fn free_space_partition(dev: &Path, min_size: u64) -> Result<Partition> {
+    let plan = plan_free_space(dev, min_size)?;
+
+    Ok(Partition {
+        path: None,
+        parent_path: Some(dev.to_path_buf()),
+        fs_type: Some("ext4".to_owned()),
+        size: plan.system_size(),
+    })
+}
+
+/// Create the system partition (and an ESP if there is none yet) in the largest unallocated
+/// region of `dev`, keeping every existing partition
+pub fn create_partition_in_free_space(dev: &Path, min_size: u64) -> Result<Partition> {
+    right_combine(Some(dev))?;
+
+    let plan = plan_free_space(dev, min_size)?;
+    let mut device = libparted::Device::new(dev)?;
+    let device = &mut device as *mut Device;
+    let device = unsafe { &mut (*device) };
+
+    if plan.needs_esp {
+        let efi = &PartitionCreate {
+            path: dev.to_path_buf(),
+            start_sector: plan.start_sector,
+            end_sector: plan.system_start(),
+            format: true,
+            file_system: Some(FileSystem::Fat32),
+            kind: PartitionType::Primary,
+            flags: vec![
+                PedPartitionFlag::PED_PARTITION_BOOT,
+                PedPartitionFlag::PED_PARTITION_ESP,
+            ],
+            label: None,
+        };
+        create_partition(device, efi)?;
+
+        let esp = find_esp_partition(dev)?;
+        format_partition(&Partition {
+            fs_type: Some("vfat".to_string()),
+            ..esp
+        })?;
+    }
+
+    let start_sector = plan.system_start();
+    let system = &PartitionCreate {
+        path: dev.to_path_buf(),
+        start_sector,
+        end_sector: plan.end_sector,
+        format: true,
+        file_system: Some(FileSystem::Ext4),
+        kind: plan.kind,
+        flags: vec![],
+        label: None,
+    };
+    create_partition(device, system)?;
+
+    let mut device = libparted::Device::new(dev)?;
+    let disk = libparted::Disk::new(&mut device)?;
+    let path = disk
+        .get_partition_by_sector(start_sector as i64)
+        .and_then(|x| x.get_path().map(|x| x.to_path_buf()))
+        .ok_or_else(|| anyhow!("Could not find partition by sector: {start_sector}"))?;
+
+    let p = Partition {
+        path: Some(path),
+        parent_path: Some(dev.to_path_buf()),
+        fs_type: Some("ext4".to_owned()),
+        size: plan.system_size(),
+    };
+
+    format_partition(&p)?;
+
+    Ok(p)
+}
+
 #[cfg(not(debug_assertions))]
 fn remove_part_by_nums(dev: &Path, nums: Vec<u32>) -> Result<()> {
     let mut device = libparted::Device::new(dev)?;
@@ -911,7 +1677,7 @@ pub fn commit(disk: &mut Disk) -> io::Result<()> {
 
 #[test]
 fn test_fs_recommendation() {
-    assert_eq!(get_recommended_fs_type("btrfs"), "ext4");
+    assert_eq!(get_recommended_fs_type("btrfs"), "btrfs");
     assert_eq!(get_recommended_fs_type("ext2"), "ext4");
     assert_eq!(get_recommended_fs_type("xfs"), "xfs");
 }
@@ -935,3 +1701,110 @@ fn test_device_is_match() {
     assert!(!device_is_sata(Path::new("/dev/sr0")));
     assert!(!device_is_sdcard(Path::new("/dev/sr0")));
 }
+
+#[test]
+fn test_set_fs_type() {
+    let part = Partition {
+        path: Some(PathBuf::from("/dev/sda2")),
+        parent_path: Some(PathBuf::from("/dev/sda")),
+        fs_type: Some("ntfs".to_string()),
+        size: 0,
+    };
+    assert_eq!(
+        set_fs_type(&part, "btrfs").unwrap().fs_type.as_deref(),
+        Some("btrfs")
+    );
+    assert!(set_fs_type(&part, "ntfs").is_err());
+    assert_eq!(btrfs_mount_options("@home"), "subvol=@home,compress=zstd:1");
+}
+
+#[test]
+fn test_parse_lvs() {
+    let data = r#"{
+      "report": [
+          {
+              "lv": [
+                  {"lv_path":"/dev/aosc/root", "lv_size":"53687091200", "devices":"/dev/sda2(0)"},
+                  {"lv_path":"", "lv_size":"1073741824", "devices":"pool_tdata(0)"}
+              ]
+          }
+      ]
+  }"#;
+    let lvs = parse_lvs(data).unwrap();
+    assert_eq!(lvs.len(), 1);
+    assert_eq!(lvs[0].lv_path, "/dev/aosc/root");
+    assert_eq!(lvs[0].lv_size, "53687091200");
+    assert_eq!(lvs[0].devices, "/dev/sda2(0)");
+}
+
+#[test]
+fn test_check_mount_points() {
+    let partition = |path: &str, fs_type: Option<&str>| Partition {
+        path: Some(PathBuf::from(path)),
+        parent_path: Some(PathBuf::from("/dev/sda")),
+        fs_type: fs_type.map(|x| x.to_string()),
+        size: 0,
+    };
+    let mount_point =
+        |path: &str, mount_path: &str, fs_type: Option<&str>, format: bool| MountPoint {
+            partition: partition(path, fs_type),
+            mount_path: PathBuf::from(mount_path),
+            format,
+        };
+    let root = partition("/dev/sda1", Some("ext4"));
+    let home = mount_point("/dev/sda2", "/home", Some("ext4"), false);
+    let var = mount_point("/dev/sda3", "/var", Some("xfs"), true);
+    assert!(check_mount_points(&root, None, &[home.clone(), var.clone()]).is_ok());
+    // /home is a subvolume of Btrfs root partitions
+    let btrfs_root = partition("/dev/sda1", Some("btrfs"));
+    assert!(check_mount_points(&btrfs_root, None, std::slice::from_ref(&home)).is_err());
+    assert!(check_mount_points(
+        &root,
+        Some(&LvmLayout { root_size: Some(1) }),
+        std::slice::from_ref(&home)
+    )
+    .is_err());
+    assert!(check_mount_points(&root, Some(&LvmLayout { root_size: None }), &[home]).is_ok());
+    for mount_path in ["var", "/", "/var/../etc", "/efi", "/proc/foo"] {
+        let mount_point = mount_point("/dev/sda3", mount_path, Some("ext4"), true);
+        assert!(check_mount_points(&root, None, &[mount_point]).is_err());
+    }
+    let srv = mount_point("/dev/sda3", "/srv", Some("ext4"), false);
+    assert!(check_mount_points(&root, None, &[var.clone(), srv]).is_err());
+    let var_again = mount_point("/dev/sda4", "/var", Some("ext4"), false);
+    assert!(check_mount_points(&root, None, &[var, var_again]).is_err());
+    let root_again = mount_point("/dev/sda1", "/srv", Some("ext4"), false);
+    assert!(check_mount_points(&root, None, &[root_again]).is_err());
+    let unformatted = mount_point("/dev/sda3", "/srv", None, false);
+    assert!(check_mount_points(&root, None, &[unformatted]).is_err());
+    let unsupported = mount_point("/dev/sda3", "/srv", Some("ntfs"), true);
+    assert!(check_mount_points(&root, None, &[unsupported]).is_err());
+}
+
+#[test]
+fn test_align_free_region() {
+    // 1 MiB is 2048 sectors of 512 bytes
+    assert_eq!(align_free_region(2048, 4096, 512), Some((2048, 6144)));
+    assert_eq!(align_free_region(100, 10000, 512), Some((2048, 8192)));
+    assert_eq!(align_free_region(34, 2000, 512), None);
+    assert_eq!(align_free_region(8, 512, 4096), Some((256, 512)));
+}
+
+#[test]
+fn test_free_region_kind() {
+    let extended = Some((2048, 10240));
+    assert_eq!(
+        free_region_kind((4096, 8192), extended, 4, 4, 1),
+        Some(PartitionType::Logical)
+    );
+    assert_eq!(
+        free_region_kind((20480, 40960), extended, 3, 4, 1),
+        Some(PartitionType::Primary)
+    );
+    assert_eq!(free_region_kind((20480, 40960), extended, 4, 4, 1), None);
+    assert_eq!(free_region_kind((20480, 40960), None, 3, 4, 2), None);
+    assert_eq!(
+        free_region_kind((20480, 40960), None, 5, 128, 2),
+        Some(PartitionType::Primary)
+    );
+}
@@ -0,0 +1,646 @@
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::{Read, Write},
+    ops::Range,
+    os::unix::fs::FileExt,
+    sync::mpsc::Sender,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use cursive::utils::Counter;
+use log::{info, warn};
+use reqwest::{header::RANGE, Client, StatusCode};
+use sha2::{Digest, Sha256};
+
+use crate::{checksum::ChecksumWriter, network::Mirror};
+
+const MAX_RETRIES: u32 = 5;
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+const MIN_SEGMENT_SIZE: usize = 64 * 1024 * 1024;
+const HASH_READ_SIZE: usize = 1024 * 1024;
+// how much a rate limited download may catch up after a stall (e.g. a retry)
+const RATE_LIMIT_BURST: Duration = Duration::from_secs(1);
+
+/// Why a download attempt stopped before the whole range was written
+enum Interrupted {
+    /// Network failure, it is worth trying again from the current offset
+    Retry(anyhow::Error),
+    /// The mirror can not serve this file (e.g. a 404), try the next mirror
+    Unusable(anyhow::Error),
+    /// Local failure (e.g. a write error), give up immediately
+    Fatal(anyhow::Error),
+}
+
+/// Computes download speed and ETA about once a second
+struct SpeedMeter {
+    timer: Instant,
+    bytes: usize,
+}
+
+impl SpeedMeter {
+    fn new() -> Self {
+        Self {
+            timer: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    /// Record `len` newly received bytes, returns formatted (speed, eta) once per second
+    fn update(&mut self, len: usize, done: usize, total: usize) -> Option<(String, String)> {
+        self.bytes += len;
+        let elapsed = self.timer.elapsed().as_secs_f64();
+        if elapsed < 1.0 {
+            return None;
+        }
+
+        let speed = self.bytes as f64 / 1024.0 / elapsed;
+        let eta = total.saturating_sub(done) as f64 / 1024.0 / speed;
+        self.bytes = 0;
+        self.timer = Instant::now();
+
+        Some(calc_speed(speed, eta))
+    }
+}
+
+/// Keeps the total download speed under `limit` bytes per second
+struct RateLimiter {
+    limit: u64,
+    /// When the bytes received so far are allowed to have arrived
+    next: Instant,
+}
+
+impl RateLimiter {
+    fn new(limit: u64) -> Self {
+        Self {
+            limit: limit.max(1),
+            next: Instant::now(),
+        }
+    }
+
+    /// Record `len` newly received bytes, returns how long to pause before reading more
+    fn delay(&mut self, len: usize) -> Duration {
+        let now = Instant::now();
+        let earliest = now.checked_sub(RATE_LIMIT_BURST).unwrap_or(now);
+        self.next =
+            self.next.max(earliest) + Duration::from_secs_f64(len as f64 / self.limit as f64);
+
+        self.next.saturating_duration_since(now)
+    }
+}
+
+/// Where the downloaded data goes
+#[derive(Clone, Copy)]
+enum Output<'a> {
+    /// A preallocated file, written at the offset of every chunk
+    File(&'a File),
+    /// Passed on in order to a `StreamReader`
+    Stream(&'a tokio::sync::mpsc::Sender<Vec<u8>>),
+}
+
+/// Shared state of all ranges being downloaded into the same file
+struct Download<'a> {
+    client: &'a Client,
+    path: &'a str,
+    output: Output<'a>,
+    file_size: usize,
+    counter: &'a Counter,
+    meter: RefCell<SpeedMeter>,
+    speed_tx: &'a Sender<(String, String)>,
+    limiter: Option<RefCell<RateLimiter>>,
+    flush_writes: bool,
+}
+
+/// Download `path` from the first usable mirror in `mirrors` into the preallocated `output` file.
+///
+/// Every chunk is written to `output` before being passed on to `checksum`, so
+/// when the connection breaks the download resumes with a `Range` request from the last
+/// written offset and the streaming checksum stays correct. If a mirror keeps failing,
+/// the download continues from the same offset on the next mirror.
+///
+/// With `rate_limit` (bytes per second), reading from the network pauses as needed to stay under it.
+/// With `flush_writes`, the output is flushed after every chunk (for low memory Retro devices).
+#[allow(clippy::too_many_arguments)]
+pub async fn download_file(
+    client: &Client,
+    mirrors: &[Mirror],
+    path: &str,
+    output: &File,
+    file_size: usize,
+    counter: &Counter,
+    speed_tx: &Sender<(String, String)>,
+    checksum: &mut ChecksumWriter,
+    rate_limit: Option<u64>,
+    flush_writes: bool,
+) -> Result<()> {
+    let download = Download {
+        client,
+        path,
+        output: Output::File(output),
+        file_size,
+        counter,
+        meter: RefCell::new(SpeedMeter::new()),
+        speed_tx,
+        limiter: rate_limit.map(|x| RefCell::new(RateLimiter::new(x))),
+        flush_writes,
+    };
+
+    download
+        .download_range(mirrors, 0..file_size, Some(checksum))
+        .await
+}
+
+/// Download `path` like `download_file`, but pass the data on in order to `stream_tx`
+/// instead of writing it to a file, so it can be unpacked while downloading.
+///
+/// `stream_tx` should be bounded, so that a slow disk holds back the download
+/// instead of piling up chunks in memory.
+#[allow(clippy::too_many_arguments)]
+pub async fn download_stream(
+    client: &Client,
+    mirrors: &[Mirror],
+    path: &str,
+    file_size: usize,
+    counter: &Counter,
+    speed_tx: &Sender<(String, String)>,
+    stream_tx: &tokio::sync::mpsc::Sender<Vec<u8>>,
+    rate_limit: Option<u64>,
+) -> Result<()> {
+    let download = Download {
+        client,
+        path,
+        output: Output::Stream(stream_tx),
+        file_size,
+        counter,
+        meter: RefCell::new(SpeedMeter::new()),
+        speed_tx,
+        limiter: rate_limit.map(|x| RefCell::new(RateLimiter::new(x))),
+        flush_writes: false,
+    };
+
+    download.download_range(mirrors, 0..file_size, None).await
+}
+
+/// Reads the data sent by `download_stream`, calculating its checksum on the way
+pub struct StreamReader {
+    rx: tokio::sync::mpsc::Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+    hasher: Sha256,
+}
+
+impl StreamReader {
+    pub fn new(rx: tokio::sync::mpsc::Receiver<Vec<u8>>) -> Self {
+        Self {
+            rx,
+            chunk: vec![],
+            pos: 0,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Read the rest of the stream (e.g. the padding after the end of an archive),
+    /// returns the SHA-256 checksum of everything received
+    pub fn finish(mut self) -> Result<String> {
+        std::io::copy(&mut self, &mut std::io::sink())?;
+
+        Ok(hex::encode(self.hasher.finalize()))
+    }
+}
+
+impl Read for StreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos >= self.chunk.len() {
+            match self.rx.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                // the download has ended, successfully or not
+                None => return Ok(0),
+            }
+        }
+
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.hasher.update(&buf[..n]);
+        self.pos += n;
+
+        Ok(n)
+    }
+}
+
+/// Split the file into ranges and download them concurrently from the first `segments`
+/// mirrors, each range falling back to the other mirrors on failure.
+///
+/// The ranges arrive out of order, so the file is read back and fed to `checksum`
+/// once all of them are written. `rate_limit` applies to all ranges combined.
+#[allow(clippy::too_many_arguments)]
+pub async fn download_file_segmented(
+    client: &Client,
+    mirrors: &[Mirror],
+    path: &str,
+    output: &File,
+    file_size: usize,
+    counter: &Counter,
+    speed_tx: &Sender<(String, String)>,
+    checksum: &mut ChecksumWriter,
+    segments: usize,
+    rate_limit: Option<u64>,
+    flush_writes: bool,
+) -> Result<()> {
+    let segments = segments
+        .min(mirrors.len())
+        .min(file_size / MIN_SEGMENT_SIZE)
+        .max(1);
+
+    if segments == 1 {
+        return download_file(
+            client,
+            mirrors,
+            path,
+            output,
+            file_size,
+            counter,
+            speed_tx,
+            checksum,
+            rate_limit,
+            flush_writes,
+        )
+        .await;
+    }
+
+    let download = Download {
+        client,
+        path,
+        output: Output::File(output),
+        file_size,
+        counter,
+        meter: RefCell::new(SpeedMeter::new()),
+        speed_tx,
+        limiter: rate_limit.map(|x| RefCell::new(RateLimiter::new(x))),
+        flush_writes,
+    };
+
+    let segment_size = file_size / segments;
+    let mut tasks = vec![];
+    for i in 0..segments {
+        let start = i * segment_size;
+        let end = if i == segments - 1 {
+            file_size
+        } else {
+            start + segment_size
+        };
+        // every range starts on its own mirror, the others are its fallbacks
+        let mut order = mirrors.to_vec();
+        order.rotate_left(i);
+        info!(
+            "Segment {}: bytes {}-{} from mirror {}",
+            i,
+            start,
+            end - 1,
+            order[0].name
+        );
+
+        let download = &download;
+        tasks.push(async move { download.download_range(&order, start..end, None).await });
+    }
+
+    futures::future::try_join_all(tasks).await?;
+
+    info!("All segments downloaded, calculating checksum ...");
+    hash_file(output, file_size, checksum).await
+}
+
+/// Feed the downloaded file to the checksum worker in order
+async fn hash_file(output: &File, file_size: usize, checksum: &mut ChecksumWriter) -> Result<()> {
+    let mut offset = 0;
+    let mut buf = vec![0; HASH_READ_SIZE];
+    while offset < file_size {
+        let len = HASH_READ_SIZE.min(file_size - offset);
+        output.read_exact_at(&mut buf[..len], offset as u64)?;
+        offset += len;
+        checksum.write(&buf[..len]).await?;
+    }
+
+    Ok(())
+}
+
+impl Download<'_> {
+    /// Download `range` from the first usable mirror in `mirrors`.
+    ///
+    /// When a mirror stalls (see the client's read timeout), the range is resumed
+    /// from the last written offset instead of waiting on it forever.
+    async fn download_range(
+        &self,
+        mirrors: &[Mirror],
+        range: Range<usize>,
+        mut checksum: Option<&mut ChecksumWriter>,
+    ) -> Result<()> {
+        let mut offset = range.start;
+        let mut last_error = anyhow!("No mirror available.");
+
+        for mirror in mirrors {
+            let url = format!("{}{}", mirror.url, self.path);
+            let start = offset;
+            info!(
+                "Downloading {} from mirror {} at offset {}",
+                url, mirror.name, offset
+            );
+
+            let result = self
+                .download_from_mirror(&url, &mut offset, range.end, checksum.as_deref_mut())
+                .await;
+
+            if offset > start {
+                info!(
+                    "Mirror {} served bytes {}-{}",
+                    mirror.name,
+                    start,
+                    offset - 1
+                );
+            }
+
+            match result {
+                Ok(()) => return Ok(()),
+                Err(Interrupted::Fatal(e)) => return Err(e),
+                Err(Interrupted::Retry(e)) | Err(Interrupted::Unusable(e)) => {
+                    warn!("Mirror {} failed: {}", mirror.name, e);
+                    last_error = e;
+                }
+            }
+        }
+
+        Err(anyhow!(
+            "Installer failed to download system release from all available mirrors:\n\n{}",
+            last_error
+        ))
+    }
+
+    /// Download from a single mirror, retrying with a bounded backoff while the network is flaky
+    async fn download_from_mirror(
+        &self,
+        url: &str,
+        offset: &mut usize,
+        end: usize,
+        mut checksum: Option<&mut ChecksumWriter>,
+    ) -> Result<(), Interrupted> {
+        let mut retries = 0;
+
+        loop {
+            let last_offset = *offset;
+            let e = match self.fetch(url, offset, end, checksum.as_deref_mut()).await {
+                Err(Interrupted::Retry(e)) => e,
+                result => return result,
+            };
+
+            // only give up if we are stuck at the same offset for a while
+            if *offset > last_offset {
+                retries = 0;
+            }
+            if retries >= MAX_RETRIES {
+                return Err(Interrupted::Retry(anyhow!(
+                    "giving up after {} retries: {}",
+                    MAX_RETRIES,
+                    e
+                )));
+            }
+
+            let delay = RETRY_BASE_DELAY
+                .saturating_mul(2_u32.pow(retries))
+                .min(RETRY_MAX_DELAY);
+            retries += 1;
+            warn!(
+                "Download interrupted at {}/{} bytes: {}, retrying in {}s ({}/{})",
+                offset,
+                end,
+                e,
+                delay.as_secs(),
+                retries,
+                MAX_RETRIES
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Issue one request for `offset..end` and write everything we receive
+    async fn fetch(
+        &self,
+        url: &str,
+        offset: &mut usize,
+        end: usize,
+        mut checksum: Option<&mut ChecksumWriter>,
+    ) -> Result<(), Interrupted> {
+        let mut request = self.client.get(url);
+        let is_range = *offset > 0 || end < self.file_size;
+        if is_range {
+            info!("Requesting bytes {}-{}", offset, end - 1);
+            request = request.header(RANGE, format!("bytes={}-{}", offset, end - 1));
+        }
+
+        let resp = request
+            .send()
+            .await
+            .map_err(|e| Interrupted::Retry(e.into()))?;
+
+        let status = resp.status();
+        if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+            return Err(Interrupted::Retry(anyhow!("Server returned {status}")));
+        }
+        let mut resp = resp
+            .error_for_status()
+            .map_err(|e| Interrupted::Unusable(e.into()))?;
+
+        // The server may ignore our Range header, skip what we already have in that case
+        let mut skip = if is_range && status != StatusCode::PARTIAL_CONTENT {
+            warn!("Server does not support range requests, downloading from the beginning");
+            *offset
+        } else {
+            0
+        };
+
+        while *offset < end {
+            let chunk = match resp.chunk().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => {
+                    return Err(Interrupted::Retry(anyhow!(
+                        "The remote closed the connection prematurely."
+                    )))
+                }
+                // a stalled mirror ends up here through the client's read timeout
+                Err(e) if e.is_timeout() => {
+                    return Err(Interrupted::Retry(anyhow!(
+                        "The remote stopped sending data: {e}"
+                    )))
+                }
+                Err(e) => return Err(Interrupted::Retry(e.into())),
+            };
+
+            let n = skip.min(chunk.len());
+            skip -= n;
+            let chunk = chunk.slice(n..chunk.len().min(n + end - *offset));
+            if chunk.is_empty() {
+                continue;
+            }
+
+            match self.output {
+                Output::File(output) => output
+                    .write_all_at(&chunk, *offset as u64)
+                    .map_err(|e| Interrupted::Fatal(e.into()))?,
+                Output::Stream(stream_tx) => {
+                    stream_tx.send(chunk.to_vec()).await.map_err(|_| {
+                        Interrupted::Fatal(anyhow!(
+                            "Installer stopped unpacking the system release."
+                        ))
+                    })?
+                }
+            }
+            *offset += chunk.len();
+            self.counter.tick(chunk.len());
+            if let Some(checksum) = checksum.as_deref_mut() {
+                checksum.write(&chunk).await.map_err(Interrupted::Fatal)?;
+            }
+
+            // pausing here also holds back the server through TCP flow control,
+            // and the pause is counted in the measured speed
+            if let Some(limiter) = &self.limiter {
+                let delay = limiter.borrow_mut().delay(chunk.len());
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+            }
+
+            let speed =
+                self.meter
+                    .borrow_mut()
+                    .update(chunk.len(), self.counter.get(), self.file_size);
+            if let Some(speed) = speed {
+                self.speed_tx.send(speed).ok();
+            }
+
+            if let (true, Output::File(mut output)) = (self.flush_writes, self.output) {
+                // 确保在 Retro 设备上不会因为网速过快，数据来不及写入硬盘导致的 OOM
+                output.flush().map_err(|e| Interrupted::Fatal(e.into()))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn calc_speed(speed: f64, eta: f64) -> (String, String) {
+    let s = match speed {
+        x if x > 1000.0 * 1000.0 => format!("{:.1}GiB/s", speed / 1024.0 / 1024.0),
+        x if x > 1000.0 => format!("{:.1}MiB/s", speed / 1024.0),
+        x => format!("{x:.1}KiB/s"),
+    };
+
+    let s2 = match eta {
+        x if x >= 60.0 * 60.0 * 24.0 => format!("{:.1}d", (x / 60.0 / 60.0 / 24.0).round()),
+        x if x >= 60.0 * 60.0 => format!("{:.1}h", (x / 60.0 / 60.0).round()),
+        x if x >= 60.0 => format!("{:.1}m", (eta / 60.0).round()),
+        x => format!("{:.0}s", x.round()),
+    };
+
+    (s, s2)
+}
+
+#[test]
+fn test_rate_limiter() {
+    let mut limiter = RateLimiter::new(1024 * 1024);
+    let delay = limiter.delay(512 * 1024);
+    assert!(delay > Duration::from_millis(400) && delay <= Duration::from_millis(500));
+    // the pauses add up while data keeps arriving faster than the limit
+    let delay = limiter.delay(512 * 1024);
+    assert!(delay > Duration::from_millis(900) && delay <= Duration::from_secs(1));
+}
+
+#[test]
+fn test_stream_reader() {
+    let (tx, rx) = tokio::sync::mpsc::channel(4);
+    tx.blocking_send(b"hello ".to_vec()).unwrap();
+    tx.blocking_send(b"world".to_vec()).unwrap();
+    drop(tx);
+
+    let mut reader = StreamReader::new(rx);
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello wo");
+    assert_eq!(
+        reader.finish().unwrap(),
+        "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+    );
+}
+
+#[test]
+fn test_resume_stalled_download() {
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        let mut requests = vec![];
+        for response in [
+            // stalls after half of the file
+            "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello",
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 5-9/10\r\nContent-Length: 5\r\n\r\nworld",
+        ] {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            while reader.read_line(&mut request).unwrap() > 2 {}
+            requests.push(request.to_lowercase());
+            stream.write_all(response.as_bytes()).unwrap();
+            // keep the connection open without sending anything
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_secs(2));
+                drop(stream);
+            });
+        }
+        requests
+    });
+
+    let client = Client::builder()
+        .read_timeout(Duration::from_millis(200))
+        .build()
+        .unwrap();
+    let mirror = Mirror {
+        name: "stalled".to_string(),
+        name_tr: String::new(),
+        loc: String::new(),
+        loc_tr: String::new(),
+        url,
+    };
+    let (speed_tx, _speed_rx) = std::sync::mpsc::channel();
+    let (stream_tx, stream_rx) = tokio::sync::mpsc::channel(4);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime
+        .block_on(download_stream(
+            &client,
+            &[mirror],
+            "file",
+            10,
+            &Counter::new(0),
+            &speed_tx,
+            &stream_tx,
+            None,
+        ))
+        .unwrap();
+    drop(stream_tx);
+
+    let mut buf = String::new();
+    StreamReader::new(stream_rx)
+        .read_to_string(&mut buf)
+        .unwrap();
+    assert_eq!(buf, "helloworld");
+    let requests = server.join().unwrap();
+    assert!(requests[1].contains("range: bytes=5-9"));
+}
@@ -1,4 +1,5 @@
 use std::{
+    io::{BufRead, BufReader, Write},
     path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
@@ -9,20 +10,51 @@ use std::{
 };
 
 use crate::{
-    disks::{self, Partition},
+    cache::{DownloadCache, DEFAULT_CACHE_DIR},
+    disks::{self, MountPoint, Partition},
     install::{self, is_acceptable_username, is_valid_hostname, umount_all},
-    network::{self, fetch_mirrors, Mirror, VariantEntry},
+    network::{self, fetch_mirrors, Bulletin, InstallSource, Mirror, NetworkConfig, VariantEntry},
 };
 use anyhow::{anyhow, Result};
 use clap::{Parser, Subcommand};
 use indicatif::ProgressBar;
 use log::{error, info};
+use rustix::termios::{tcgetattr, tcsetattr, LocalModes, OptionalActions};
+use serde::Serialize;
+use serde_json::json;
 
 use super::{begin_install, tui_main, AtomicBoolWrapper, InstallConfig, DEFAULT_EMPTY_SIZE};
 
+const FASTEST_MIRROR: &str = "fastest";
+
 #[derive(Parser, Debug)]
 #[clap(about, version, author)]
 pub struct Args {
+    /// Fetch the release recipe from this URL instead of the official one
+    #[clap(long, global = true)]
+    recipe_url: Option<String>,
+    /// Use this proxy for all network requests (e.g., http://proxy:3128, socks5h://127.0.0.1:1080),
+    /// defaults to the http_proxy, https_proxy and all_proxy environment variables
+    #[clap(long, global = true)]
+    proxy: Option<String>,
+    /// Trust the CA certificates in this PEM bundle for HTTPS connections (may be repeated)
+    #[clap(long, global = true)]
+    ca_cert: Vec<PathBuf>,
+    /// Trust the CA certificates installed on the live system
+    #[clap(long, global = true, action = clap::ArgAction::SetTrue)]
+    system_ca: bool,
+    /// Limit the download speed of system releases (MiB/s)
+    #[clap(long, global = true)]
+    limit_rate: Option<f64>,
+    /// Do not verify the signature of the release recipe (insecure)
+    #[clap(long, global = true, action = clap::ArgAction::SetTrue)]
+    allow_unsigned_recipe: bool,
+    /// Install AOSC OS/Retro (default on architectures only supported by Retro)
+    #[clap(long, global = true, conflicts_with = "mainline", action = clap::ArgAction::SetTrue)]
+    retro: bool,
+    /// Install mainline AOSC OS, even on architectures that default to Retro
+    #[clap(long, global = true, action = clap::ArgAction::SetTrue)]
+    mainline: bool,
     #[clap(subcommand)]
     subcommand: DeployKitCliCommand,
 }
@@ -41,40 +73,172 @@ enum DeployKitCliCommand {
     ListTimezone(ListTimezone),
     /// List of tarball
     ListTarball(ListTarball),
+    /// List of partitions and LVM logical volumes to install to
+    ListPartition(ListPartition),
+    /// Manage the download cache
+    #[clap(subcommand)]
+    Cache(CacheCommand),
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheCommand {
+    /// List cached system releases
+    List(CacheOptions),
+    /// Remove cached system releases
+    Clean(CacheClean),
+}
+
+#[derive(Parser, Debug)]
+struct CacheOptions {
+    /// Directory of the download cache
+    #[clap(long, default_value = DEFAULT_CACHE_DIR)]
+    cache_dir: PathBuf,
+}
+
+#[derive(Parser, Debug)]
+struct CacheClean {
+    #[clap(flatten)]
+    options: CacheOptions,
+    /// Only remove the least recently used releases until the cache fits in this size (GiB)
+    #[clap(long)]
+    keep_size: Option<f64>,
 }
 
 #[derive(Parser, Debug)]
 struct Tui;
 
 #[derive(Parser, Debug)]
-struct ListMirror;
+struct ListMirror {
+    /// Benchmark the mirrors and list them from the fastest to the slowest
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    benchmark: bool,
+    /// Print full records as JSON
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    json: bool,
+}
+
+#[derive(Parser, Debug)]
+struct ListLocale {
+    /// Print full records as JSON
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    json: bool,
+}
 
 #[derive(Parser, Debug)]
-struct ListLocale;
+struct ListTimezone {
+    /// Print full records as JSON
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    json: bool,
+}
 
 #[derive(Parser, Debug)]
-struct ListTimezone;
+struct ListPartition {
+    /// Print full records as JSON
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    json: bool,
+}
 
 #[derive(Parser, Debug)]
-struct ListTarball;
+struct ListTarball {
+    /// Do not print the bulletin from the release recipe
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    no_bulletin: bool,
+    /// Print full records as JSON, including every release of each variant
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    json: bool,
+}
 
 #[derive(Parser, Debug)]
 struct InstallCommand {
     /// Select AOSC OS variant to install (e.g., Workstation, Server, Base)
     #[clap(long, default_value = "Base")]
     tarball: String,
-    /// Set URL for download source
+    /// Install the release of the variant built on this date (e.g., 20240101) instead of the newest one
+    #[clap(long)]
+    date: Option<String>,
+    /// Set download source: a URL, the name or location of a mirror from `list-mirror`,
+    /// or "fastest" to benchmark the mirrors and use the fastest one
     #[clap(long, default_value = "https://repo.aosc.io/aosc-os")]
     mirror: String,
+    /// Install from a local directory (or file:// URL) containing recipe.json and system releases
+    #[clap(long, conflicts_with = "mirror")]
+    source: Option<String>,
+    /// Do not fall back to other mirrors if the download source fails
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    no_mirror_fallback: bool,
+    /// Download segments of the system release from this many top ranked mirrors in parallel
+    #[clap(long, conflicts_with = "no_mirror_fallback")]
+    parallel_mirrors: Option<usize>,
+    /// Do not print the bulletin from the release recipe
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    no_bulletin: bool,
+    /// Unpack the system release (tarballs only) while downloading it,
+    /// without saving a copy to the target partition first
+    #[clap(long, conflicts_with = "parallel_mirrors", action = clap::ArgAction::SetTrue)]
+    stream_extract: bool,
+    /// Install a system release not listed in the recipe, from a URL or a local file
+    #[clap(
+        long,
+        requires = "sha256",
+        conflicts_with_all = ["tarball", "date", "mirror", "source", "parallel_mirrors"]
+    )]
+    rootfs: Option<String>,
+    /// SHA-256 checksum of the system release given by --rootfs
+    #[clap(long, requires = "rootfs")]
+    sha256: Option<String>,
+    /// Exact size of the system release given by --rootfs (bytes), instead of asking the server
+    #[clap(long, requires = "rootfs")]
+    rootfs_size: Option<u64>,
+    /// Space taken by the unpacked system release given by --rootfs (GiB), instead of an estimate
+    #[clap(long, requires = "rootfs")]
+    install_size: Option<f64>,
+    /// Reuse system releases from the download cache, and save downloaded ones to it
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    cache: bool,
+    /// Directory of the download cache
+    #[clap(long, default_value = DEFAULT_CACHE_DIR)]
+    cache_dir: PathBuf,
+    /// Evict the least recently used releases once the download cache grows beyond this size (GiB)
+    #[clap(long, requires = "cache")]
+    cache_size_limit: Option<f64>,
     /// Set target partition to install AOSC OS to (e.g., /dev/sda1)
     #[clap(long)]
     path: String,
+    /// Treat --path as a drive (e.g., /dev/sda) and create the system partition in its largest
+    /// unallocated space, keeping existing partitions and reusing an existing ESP
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    alongside: bool,
+    /// Format the target partition as ext4, xfs or btrfs (with @ and @home subvolumes),
+    /// instead of keeping its current filesystem if supported
+    #[clap(long)]
+    fs_type: Option<String>,
+    /// Encrypt the target partition with LUKS2, the passphrase is asked for on the terminal
+    /// (and at every boot)
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    luks: bool,
+    /// Read the LUKS passphrase from this file ("-" for stdin) instead of asking for it
+    #[clap(long, requires = "luks", value_name = "FILE")]
+    luks_passphrase_file: Option<PathBuf>,
+    /// Also mount a partition in the installed system: /dev/sda3:/home keeps its filesystem,
+    /// /dev/sda3:/var:xfs formats it as xfs first (may be repeated)
+    #[clap(long, value_name = "DEVICE:MOUNT_PATH[:FS_TYPE]")]
+    mount: Vec<String>,
+    /// Create an LVM volume group on the target partition, with a root logical volume
+    /// and a swap volume instead of a swapfile
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    lvm: bool,
+    /// Size of the root logical volume (GiB), the rest of the volume group goes to /home
+    #[clap(long, requires = "lvm")]
+    lvm_root_size: Option<f64>,
     /// Set name of the default user
     #[clap(long)]
     user: String,
     /// Set password for default user
     #[clap(long)]
     password: String,
+    /// Set password for root (required by AOSC OS/Retro)
+    #[clap(long)]
+    root_password: Option<String>,
     /// Set device hostname
     #[clap(long, default_value = "aosc")]
     hostname: String,
@@ -96,30 +260,96 @@ struct InstallCommand {
 }
 
 pub fn execute(args: Args) -> Result<()> {
+    let source = match args.recipe_url {
+        Some(url) => InstallSource::from_location(&url)?,
+        None => InstallSource::default(),
+    };
+    let retro = if args.retro {
+        true
+    } else if args.mainline {
+        false
+    } else {
+        network::detect_retro()
+    };
+    let network = NetworkConfig::new(args.proxy.as_deref())?
+        .with_ca_certs(args.ca_cert, args.system_ca)?
+        .with_rate_limit(args.limit_rate)?;
+
     match args.subcommand {
-        DeployKitCliCommand::Tui(Tui) => tui_main(),
-        DeployKitCliCommand::Install(ic) => start_install(*ic)?,
-        DeployKitCliCommand::ListMirror(ListMirror) => list_mirror()?,
-        DeployKitCliCommand::ListLocale(ListLocale) => list_locale()?,
-        DeployKitCliCommand::ListTimezone(ListTimezone) => list_timezone()?,
-        DeployKitCliCommand::ListTarball(ListTarball) => list_tarball()?,
+        DeployKitCliCommand::Tui(Tui) => {
+            tui_main(source, network, args.allow_unsigned_recipe, retro)
+        }
+        DeployKitCliCommand::Install(ic) => {
+            start_install(*ic, source, network, args.allow_unsigned_recipe, retro)?
+        }
+        DeployKitCliCommand::ListMirror(lm) => {
+            list_mirror(&source, &network, args.allow_unsigned_recipe, &lm)?
+        }
+        DeployKitCliCommand::ListLocale(ll) => list_locale(ll.json)?,
+        DeployKitCliCommand::ListTimezone(lt) => list_timezone(lt.json)?,
+        DeployKitCliCommand::Cache(CacheCommand::List(options)) => list_cache(&options)?,
+        DeployKitCliCommand::Cache(CacheCommand::Clean(clean)) => clean_cache(&clean)?,
+        DeployKitCliCommand::ListPartition(lp) => list_partition(lp.json)?,
+        DeployKitCliCommand::ListTarball(lt) => {
+            list_tarball(&source, &network, args.allow_unsigned_recipe, retro, &lt)?
+        }
     }
 
     Ok(())
 }
 
-fn list_mirror() -> Result<()> {
-    let recipe = network::fetch_recipe()?;
+fn list_mirror(
+    source: &InstallSource,
+    network: &NetworkConfig,
+    allow_unsigned_recipe: bool,
+    lm: &ListMirror,
+) -> Result<()> {
+    let recipe = network::fetch_recipe(source, network, allow_unsigned_recipe)?;
     let mirrors = fetch_mirrors(&recipe);
-    for i in mirrors {
-        println!("{:<40}{}", i.name, i.url);
+    if !lm.benchmark {
+        if lm.json {
+            return print_json(&mirrors);
+        }
+        for i in mirrors {
+            println!("{:<40}{}", i.name, i.url);
+        }
+
+        return Ok(());
+    }
+
+    let results = network::benchmark_mirrors(mirrors, network, |x| {
+        eprintln!("Benchmarked {}", x.mirror.name);
+    })?;
+    if lm.json {
+        return print_json(&results);
+    }
+    for i in results {
+        let score = match i.score {
+            Ok(score) => score.to_string(),
+            Err(e) => format!("failed: {e}"),
+        };
+        println!("{:<40}{:<60}{}", i.mirror.name, i.mirror.url, score);
     }
 
     Ok(())
 }
 
-fn list_locale() -> Result<()> {
+fn list_locale(json: bool) -> Result<()> {
     let locale_list = install::get_locale_list()?;
+    if json {
+        let locale_list = locale_list
+            .iter()
+            .map(|(lang, locale, lang_english)| {
+                json!({
+                    "language": lang,
+                    "language_english": lang_english,
+                    "locale": locale,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        return print_json(&locale_list);
+    }
     for (lang, locale, _) in locale_list {
         println!("{lang}: {locale}");
     }
@@ -127,8 +357,11 @@ fn list_locale() -> Result<()> {
     Ok(())
 }
 
-fn list_timezone() -> Result<()> {
+fn list_timezone(json: bool) -> Result<()> {
     let timezone_list = install::get_zoneinfo_list()?;
+    if json {
+        return print_json(&timezone_list);
+    }
     for i in timezone_list {
         println!("{i}");
     }
@@ -136,23 +369,132 @@ fn list_timezone() -> Result<()> {
     Ok(())
 }
 
-fn list_tarball() -> Result<()> {
-    let variants = network::get_variants()?;
-    for i in variants {
-        println!("{}", i.name);
+fn list_partition(json: bool) -> Result<()> {
+    let partitions = list_install_targets();
+    if json {
+        return print_json(&partitions);
+    }
+    for i in partitions {
+        println!(
+            "{:<40}{:<20}{:.3}GiB",
+            i.path.as_deref().unwrap_or(Path::new("?")).display(),
+            i.fs_type.as_deref().unwrap_or("unformatted"),
+            i.size as f64 / 1024.0 / 1024.0 / 1024.0
+        );
+    }
+
+    Ok(())
+}
+
+fn print_json<T: Serialize + ?Sized>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+
+    Ok(())
+}
+
+fn print_bulletin(bulletin: &Bulletin) {
+    if bulletin.is_empty() {
+        return;
+    }
+
+    // keep stdout clean for scripts parsing our output
+    let kind = match bulletin.type_.as_str() {
+        "warning" => "Warning",
+        "error" => "Important",
+        _ => "Notice",
+    };
+    eprintln!("[{}] {}\n{}\n", kind, bulletin.title(), bulletin.body());
+}
+
+fn list_tarball(
+    source: &InstallSource,
+    network: &NetworkConfig,
+    allow_unsigned_recipe: bool,
+    retro: bool,
+    lt: &ListTarball,
+) -> Result<()> {
+    let recipe = network::fetch_recipe(source, network, allow_unsigned_recipe)?;
+    if !lt.no_bulletin {
+        print_bulletin(&recipe.bulletin);
+    }
+    let variants = network::find_variant_candidates(recipe, retro)?;
+    if lt.json {
+        return print_json(&variants);
+    }
+    for i in network::latest_variants(&variants) {
+        let dates = variants
+            .iter()
+            .filter(|x| x.name == i.name)
+            .map(|x| x.date.as_str())
+            .collect::<Vec<_>>();
+        println!("{:<30}{}", i.name, dates.join(" "));
+    }
+
+    Ok(())
+}
+
+fn list_cache(options: &CacheOptions) -> Result<()> {
+    let cache = DownloadCache::new(&options.cache_dir, None);
+    let entries = cache.list()?;
+    for i in &entries {
+        println!(
+            "{}  {:>8.3}GiB  {}",
+            i.sha256sum,
+            i.size as f64 / 1024.0 / 1024.0 / 1024.0,
+            i.name
+        );
     }
+    let total: u64 = entries.iter().map(|x| x.size).sum();
+    println!(
+        "{} releases, {:.3}GiB in total",
+        entries.len(),
+        total as f64 / 1024.0 / 1024.0 / 1024.0
+    );
 
     Ok(())
 }
 
-fn get_variant(tarball: &str) -> Result<VariantEntry> {
-    let variants = network::get_variants()?;
+fn clean_cache(clean: &CacheClean) -> Result<()> {
+    let cache = DownloadCache::new(&clean.options.cache_dir, None);
+    let freed = match clean.keep_size {
+        Some(size) => cache.evict((size * 1024.0 * 1024.0 * 1024.0) as u64)?,
+        None => cache.clean()?,
+    };
+    println!(
+        "Freed {:.3}GiB from {}",
+        freed as f64 / 1024.0 / 1024.0 / 1024.0,
+        clean.options.cache_dir.display()
+    );
+
+    Ok(())
+}
 
-    let index = variants
+fn get_variant(
+    tarball: &str,
+    date: Option<&str>,
+    variants: &[VariantEntry],
+) -> Result<VariantEntry> {
+    // releases of a variant are sorted from the newest to the oldest
+    let releases = variants
         .iter()
-        .position(|x| x.name.to_lowercase() == tarball.to_lowercase());
-    if let Some(index) = index {
-        return Ok(variants[index].to_owned());
+        .filter(|x| x.name.to_lowercase() == tarball.to_lowercase())
+        .collect::<Vec<_>>();
+
+    match (date, releases.first()) {
+        (None, Some(variant)) => return Ok((*variant).to_owned()),
+        (Some(date), Some(_)) => {
+            if let Some(variant) = releases.iter().find(|x| x.date == date) {
+                return Ok((*variant).to_owned());
+            }
+
+            return Err(anyhow!(
+                "Installer could not find a release of variant {} built on {}.\nAvailable releases: {}",
+                tarball,
+                date,
+                releases.iter().map(|x| x.date.as_str()).collect::<Vec<_>>().join(", ")
+            ));
+        }
+        _ => (),
     }
 
     Err(anyhow!(
@@ -161,8 +503,8 @@ fn get_variant(tarball: &str) -> Result<VariantEntry> {
     ))
 }
 
-fn get_partition(path: &str, variant: &VariantEntry) -> Result<Partition> {
-    let required_size = variant.install_size + variant.size;
+fn get_partition(path: &str, fs_type: Option<&str>, variant: &VariantEntry) -> Result<Partition> {
+    let required_size = variant.required_size();
     if cfg!(debug_assertions) {
         disks::right_combine(Some(&PathBuf::from("/dev/loop30")))?;
 
@@ -174,13 +516,13 @@ fn get_partition(path: &str, variant: &VariantEntry) -> Result<Partition> {
         });
     }
     let path = Path::new(path);
-    let list_part = disks::list_partitions(None);
+    let list_part = list_install_targets();
     let index = list_part
         .iter()
         .position(|x| x.path == Some(path.to_path_buf()));
     if let Some(index) = index {
         let partition = list_part[index].to_owned();
-        if partition.size < required_size + variant.size {
+        if partition.size < required_size {
             let s = format!(
                 "The specified partition does not contain enough space to install AOSC OS release!\n\nAvailable space: {:.3}GiB\nRequired space: {:.3}GiB", 
                 partition.size as f32 / 1024.0 / 1024.0 / 1024.0,
@@ -188,7 +530,10 @@ fn get_partition(path: &str, variant: &VariantEntry) -> Result<Partition> {
             );
             return Err(anyhow!(s));
         }
-        let partition = disks::fill_fs_type(&partition, false);
+        let partition = match fs_type {
+            Some(fs_type) => disks::set_fs_type(&partition, fs_type)?,
+            None => disks::fill_fs_type(&partition, false),
+        };
         disks::right_combine(partition.parent_path.as_deref())?;
 
         return Ok(partition);
@@ -200,21 +545,142 @@ fn get_partition(path: &str, variant: &VariantEntry) -> Result<Partition> {
     ))
 }
 
-fn get_mirror(mirror: &str) -> Mirror {
-    let s = "cli_usage";
-    let mirror = if mirror.ends_with('/') {
-        mirror.to_string()
-    } else {
-        format!("{mirror}/")
-    };
+/// Plan the system partition in the free space of the drive at `path`,
+/// it is only created once the installation starts
+fn plan_alongside_partition(
+    path: &str,
+    fs_type: Option<&str>,
+    variant: &VariantEntry,
+) -> Result<Partition> {
+    if cfg!(debug_assertions) {
+        return get_partition(path, fs_type, variant);
+    }
+    let path = Path::new(path);
+    if !disks::list_devices().iter().any(|x| x.path() == path) {
+        return Err(anyhow!(
+            "Installer could not find the specified drive: {}\nPlease specify a whole drive (e.g., /dev/sda) when using --alongside.",
+            path.display()
+        ));
+    }
+    let partition = disks::free_space_partition(path, variant.required_size())?;
+
+    match fs_type {
+        Some(fs_type) => disks::set_fs_type(&partition, fs_type),
+        None => Ok(partition),
+    }
+}
+
+/// Partitions and logical volumes AOSC OS can be installed to
+fn list_install_targets() -> Vec<Partition> {
+    let mut partitions = disks::list_partitions(None);
+    partitions.extend(disks::list_logical_volumes());
+
+    partitions
+}
+
+/// Split a `--mount` argument into the device, the mount path and the filesystem to format as
+fn parse_mount_arg(arg: &str) -> Result<(PathBuf, PathBuf, Option<String>)> {
+    let mut parts = arg.splitn(3, ':');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(device), Some(mount_path), fs_type)
+            if !device.is_empty() && !mount_path.is_empty() =>
+        {
+            Ok((
+                PathBuf::from(device),
+                PathBuf::from(mount_path),
+                fs_type.filter(|x| !x.is_empty()).map(|x| x.to_string()),
+            ))
+        }
+        _ => Err(anyhow!(
+            "Invalid mount point {}, expected DEVICE:MOUNT_PATH[:FS_TYPE] (e.g., /dev/sda3:/home)",
+            arg
+        )),
+    }
+}
+
+fn get_mount_points(args: &[String]) -> Result<Vec<MountPoint>> {
+    if args.is_empty() {
+        return Ok(vec![]);
+    }
+    let partitions = list_install_targets();
+    args.iter()
+        .map(|arg| {
+            let (device, mount_path, fs_type) = parse_mount_arg(arg)?;
+            let partition = partitions
+                .iter()
+                .find(|x| x.path.as_ref() == Some(&device))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Installer could not find the specified partition: {}",
+                        device.display()
+                    )
+                })?;
+            let (partition, format) = match fs_type {
+                Some(fs_type) => (disks::set_fs_type(partition, &fs_type)?, true),
+                None => (partition.clone(), false),
+            };
+
+            Ok(MountPoint {
+                partition,
+                mount_path,
+                format,
+            })
+        })
+        .collect()
+}
+
+/// Pick a mirror by URL, by the `name` or `loc` of a mirror in the recipe,
+/// or the winner of the benchmark in `ranked_mirrors` for `fastest`
+fn get_mirror(
+    mirror: &str,
+    recipe_mirrors: &[Mirror],
+    ranked_mirrors: Option<&[Mirror]>,
+) -> Result<Mirror> {
+    if mirror.eq_ignore_ascii_case(FASTEST_MIRROR) {
+        return ranked_mirrors
+            .and_then(|x| x.first())
+            .cloned()
+            .ok_or_else(|| {
+                anyhow!(
+                    "Installer could not reach any mirror, please check your network connection."
+                )
+            });
+    }
+
+    if mirror.contains("://") {
+        let url = if mirror.ends_with('/') {
+            mirror.to_string()
+        } else {
+            format!("{mirror}/")
+        };
+        // use the real name if this is one of the mirrors in the recipe
+        if let Some(mirror) = recipe_mirrors.iter().find(|x| x.url == url) {
+            return Ok(mirror.clone());
+        }
 
-    Mirror {
-        name: s.to_string(),
-        name_tr: s.to_string(),
-        loc: s.to_string(),
-        loc_tr: s.to_string(),
-        url: mirror,
+        return Ok(Mirror {
+            name: String::from("User specified"),
+            name_tr: String::from("user-name"),
+            loc: String::from("User specified"),
+            loc_tr: String::from("user-loc"),
+            url,
+        });
     }
+
+    recipe_mirrors
+        .iter()
+        .find(|x| {
+            [&x.name, &x.name_tr, &x.loc, &x.loc_tr]
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(mirror))
+        })
+        .cloned()
+        .ok_or_else(|| {
+            anyhow!(
+                "Installer could not find mirror {}.\nPlease refer to the `aoscdk-rs list-mirror` output for a list of available mirrors.",
+                mirror
+            )
+        })
 }
 
 fn get_swap(
@@ -231,8 +697,7 @@ fn get_swap(
         let mem = sysinfo::System::new_all().total_memory();
         let size = disks::get_recommend_swap_size(mem)?;
 
-        if partition.size as f64
-            > size + variant.install_size as f64 + variant.size as f64 - DEFAULT_EMPTY_SIZE as f64
+        if partition.size as f64 > size + variant.required_size() as f64 - DEFAULT_EMPTY_SIZE as f64
         {
             (true, size, true)
         } else {
@@ -243,14 +708,182 @@ fn get_swap(
     Ok(result)
 }
 
-fn start_install(ic: InstallCommand) -> Result<()> {
+/// Pick the system release and mirrors to install from the release recipe
+#[allow(clippy::type_complexity)]
+fn select_release(
+    ic: &InstallCommand,
+    source: InstallSource,
+    network: &NetworkConfig,
+    allow_unsigned_recipe: bool,
+    retro: bool,
+) -> Result<(
+    InstallSource,
+    VariantEntry,
+    Option<Arc<Mirror>>,
+    Option<Arc<Vec<Mirror>>>,
+)> {
+    let recipe = network::fetch_recipe(&source, network, allow_unsigned_recipe)?;
+    if !ic.no_bulletin {
+        print_bulletin(&recipe.bulletin);
+    }
+    let recipe_mirrors = fetch_mirrors(&recipe);
+    let variants = network::find_variant_candidates(recipe, retro)?;
+    let variant = get_variant(&ic.tarball, ic.date.as_deref(), &variants)?;
+    let (mirror, fallback_mirrors) = match &source {
+        InstallSource::Remote(_) => {
+            let is_fastest = ic.mirror.eq_ignore_ascii_case(FASTEST_MIRROR);
+            // an explicitly chosen mirror falls back to the others in recipe order,
+            // only parallel downloads need to know which of them are the fastest
+            let ranked_mirrors = if is_fastest || ic.parallel_mirrors.is_some() {
+                info!("Benchmarking mirrors ...");
+                Some(network::speedtest_mirrors(recipe_mirrors.clone(), network))
+            } else {
+                None
+            };
+            let mirror = get_mirror(&ic.mirror, &recipe_mirrors, ranked_mirrors.as_deref())?;
+            info!("Using mirror: {} ({})", mirror.name, mirror.url);
+            println!("Using mirror: {} ({})", mirror.name, mirror.url);
+            let fallback_mirrors = if ic.no_mirror_fallback {
+                vec![]
+            } else {
+                ranked_mirrors
+                    .unwrap_or(recipe_mirrors)
+                    .into_iter()
+                    .filter(|x| x.url != mirror.url)
+                    .collect()
+            };
+
+            (Some(Arc::new(mirror)), Some(Arc::new(fallback_mirrors)))
+        }
+        InstallSource::Local(_) => (None, None),
+    };
+
+    Ok((source, variant, mirror, fallback_mirrors))
+}
+
+/// Read the LUKS passphrase from `file` ("-" for stdin), or ask for it twice on the terminal,
+/// so that it never shows up in the command line of the installer
+fn read_luks_passphrase(file: Option<&Path>) -> Result<String> {
+    let passphrase = match file {
+        Some(file) if file == Path::new("-") => {
+            let mut passphrase = String::new();
+            std::io::stdin().read_line(&mut passphrase)?;
+            passphrase
+        }
+        Some(file) => std::fs::read_to_string(file).map_err(|e| {
+            anyhow!(
+                "Installer could not read the LUKS passphrase from {}: {}",
+                file.display(),
+                e
+            )
+        })?,
+        None => {
+            let passphrase = prompt_hidden("LUKS passphrase: ")?;
+            if prompt_hidden("Confirm LUKS passphrase: ")? != passphrase {
+                return Err(anyhow!("The LUKS passphrases do not match"));
+            }
+            passphrase
+        }
+    };
+    let passphrase = passphrase
+        .strip_suffix('\n')
+        .map(|x| x.strip_suffix('\r').unwrap_or(x))
+        .unwrap_or(&passphrase);
+    if passphrase.is_empty() {
+        return Err(anyhow!("The LUKS passphrase must not be empty"));
+    }
+
+    Ok(passphrase.to_string())
+}
+
+/// Read a line from the terminal without echoing it
+fn prompt_hidden(prompt: &str) -> Result<String> {
+    let mut tty = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .map_err(|e| anyhow!("Installer could not open the terminal: {}", e))?;
+    let termios = tcgetattr(&tty)?;
+    let mut hidden = termios.clone();
+    hidden.local_modes.remove(LocalModes::ECHO);
+    tcsetattr(&tty, OptionalActions::Flush, &hidden)?;
+
+    tty.write_all(prompt.as_bytes())?;
+    let mut line = String::new();
+    let result = BufReader::new(&tty).read_line(&mut line);
+    tcsetattr(&tty, OptionalActions::Flush, &termios)?;
+    tty.write_all(b"\n")?;
+    result?;
+
+    Ok(line)
+}
+
+fn start_install(
+    ic: InstallCommand,
+    source: InstallSource,
+    network: NetworkConfig,
+    allow_unsigned_recipe: bool,
+    retro: bool,
+) -> Result<()> {
+    if retro && ic.root_password.is_none() {
+        return Err(anyhow!(
+            "AOSC OS/Retro requires a root password, use --root-password"
+        ));
+    }
+    if retro && ic.luks {
+        return Err(anyhow!(
+            "AOSC OS/Retro does not support disk encryption, as it boots without an initramfs"
+        ));
+    }
+    let luks_passphrase = if ic.luks {
+        Some(read_luks_passphrase(ic.luks_passphrase_file.as_deref())?)
+    } else {
+        None
+    };
+    if retro && ic.lvm {
+        return Err(anyhow!(
+            "AOSC OS/Retro does not support LVM layouts, as it boots without an initramfs"
+        ));
+    }
     let running = Arc::new(AtomicBool::new(true));
     let r = running.clone();
-    let variant = get_variant(&ic.tarball)?;
-    let partition = get_partition(&ic.path, &variant)?;
-    let mirror = get_mirror(&ic.mirror);
-    let tc = if ic.use_rtc { "RTC" } else { "UTC" };
-    let (use_swap, swap_size, is_hibernation) = get_swap(ic.swap_size, &partition, &variant)?;
+    let source = match &ic.source {
+        Some(local) => InstallSource::from_local(local)?,
+        None => source,
+    };
+    let (source, variant, mirror, fallback_mirrors) = match (&ic.rootfs, &ic.sha256) {
+        (Some(rootfs), Some(sha256)) => {
+            let (mirror, variant) = network::custom_rootfs(
+                rootfs,
+                sha256,
+                ic.rootfs_size,
+                ic.install_size
+                    .map(|x| (x * 1024.0 * 1024.0 * 1024.0) as u64),
+                &network,
+            )?;
+            info!("Installing system release from {}", rootfs);
+            let mirror = mirror.map(Arc::new);
+            let fallback_mirrors = mirror.as_ref().map(|_| Arc::new(vec![]));
+
+            (source, variant, mirror, fallback_mirrors)
+        }
+        _ => select_release(&ic, source, &network, allow_unsigned_recipe, retro)?,
+    };
+    let lvm = ic.lvm.then(|| disks::LvmLayout {
+        root_size: ic
+            .lvm_root_size
+            .map(|x| (x * 1024.0 * 1024.0 * 1024.0) as u64),
+    });
+    let mount_points = get_mount_points(&ic.mount)?;
+    if let Some(root_size) = ic.lvm_root_size {
+        let required_size = variant.required_size();
+        if ((root_size * 1024.0 * 1024.0 * 1024.0) as u64) < required_size {
+            return Err(anyhow!(
+                "The root logical volume is too small to install AOSC OS release!\n\nRequired space: {:.3}GiB",
+                required_size as f32 / 1024.0 / 1024.0 / 1024.0
+            ));
+        }
+    }
 
     if !is_valid_hostname(&ic.hostname) {
         return Err(anyhow!("hostname {} is not valid!", ic.hostname));
@@ -260,10 +893,21 @@ fn start_install(ic: InstallCommand) -> Result<()> {
         return Err(anyhow!("username {} is not valid!", ic.user));
     }
 
+    let partition = if ic.alongside {
+        plan_alongside_partition(&ic.path, ic.fs_type.as_deref(), &variant)?
+    } else {
+        get_partition(&ic.path, ic.fs_type.as_deref(), &variant)?
+    };
+    disks::check_mount_points(&partition, lvm.as_ref(), &mount_points)?;
+    let tc = if ic.use_rtc { "RTC" } else { "UTC" };
+    let (use_swap, swap_size, is_hibernation) = get_swap(ic.swap_size, &partition, &variant)?;
+
     let install_config = InstallConfig {
         variant: Some(Arc::new(variant)),
         partition: Some(Arc::new(partition)),
-        mirror: Some(Arc::new(mirror)),
+        mirror,
+        fallback_mirrors,
+        parallel_mirrors: ic.parallel_mirrors,
         user: Some(Arc::new(ic.user)),
         full_name: None,
         password: Some(Arc::new(ic.password)),
@@ -278,7 +922,23 @@ fn start_install(ic: InstallCommand) -> Result<()> {
         is_hibernation: Arc::new(AtomicBoolWrapper {
             v: AtomicBool::new(is_hibernation),
         }),
-        root_password: None,
+        root_password: ic.root_password.map(Arc::new),
+        source,
+        network,
+        allow_unsigned_recipe,
+        retro,
+        stream_extract: ic.stream_extract,
+        luks_passphrase: luks_passphrase.map(Arc::new),
+        luks_passphrase_needed: false,
+        lvm,
+        mount_points,
+        cache: ic.cache.then(|| {
+            DownloadCache::new(
+                &ic.cache_dir,
+                ic.cache_size_limit
+                    .map(|x| (x * 1024.0 * 1024.0 * 1024.0) as u64),
+            )
+        }),
     };
 
     let root_fd = install::get_dir_fd(Path::new("/"))?;
@@ -330,5 +990,79 @@ fn start_install(ic: InstallCommand) -> Result<()> {
 
 #[test]
 fn test() {
-    dbg!(list_tarball().unwrap());
+    dbg!(list_tarball(
+        &InstallSource::default(),
+        &NetworkConfig::default(),
+        true,
+        network::detect_retro(),
+        &ListTarball {
+            no_bulletin: false,
+            json: false,
+        }
+    )
+    .unwrap());
+}
+
+#[test]
+fn test_get_mirror() {
+    let mirrors = vec![
+        Mirror {
+            name: "AOSC".to_string(),
+            name_tr: "aosc-name".to_string(),
+            loc: "Hong Kong".to_string(),
+            loc_tr: "hk-loc".to_string(),
+            url: "https://repo.aosc.io/aosc-os/".to_string(),
+        },
+        Mirror {
+            name: "TUNA".to_string(),
+            name_tr: "tuna-name".to_string(),
+            loc: "Beijing".to_string(),
+            loc_tr: "beijing-loc".to_string(),
+            url: "https://mirrors.tuna.tsinghua.edu.cn/anthon/aosc-os/".to_string(),
+        },
+    ];
+
+    assert_eq!(get_mirror("tuna", &mirrors, None).unwrap().name, "TUNA");
+    assert_eq!(
+        get_mirror("Hong Kong", &mirrors, None).unwrap().name,
+        "AOSC"
+    );
+    assert_eq!(
+        get_mirror("https://repo.aosc.io/aosc-os", &mirrors, None)
+            .unwrap()
+            .name,
+        "AOSC"
+    );
+    assert_eq!(
+        get_mirror("https://example.com/aosc-os", &mirrors, None)
+            .unwrap()
+            .url,
+        "https://example.com/aosc-os/"
+    );
+    assert_eq!(
+        get_mirror("fastest", &mirrors, Some(&mirrors[1..]))
+            .unwrap()
+            .name,
+        "TUNA"
+    );
+    assert!(get_mirror("fastest", &mirrors, Some(&[])).is_err());
+    assert!(get_mirror("nowhere", &mirrors, None).is_err());
+}
+
+#[test]
+fn test_parse_mount_arg() {
+    assert_eq!(
+        parse_mount_arg("/dev/sda3:/home").unwrap(),
+        (PathBuf::from("/dev/sda3"), PathBuf::from("/home"), None)
+    );
+    assert_eq!(
+        parse_mount_arg("/dev/sda3:/var:xfs").unwrap(),
+        (
+            PathBuf::from("/dev/sda3"),
+            PathBuf::from("/var"),
+            Some("xfs".to_string())
+        )
+    );
+    assert!(parse_mount_arg("/dev/sda3").is_err());
+    assert!(parse_mount_arg(":/home").is_err());
 }
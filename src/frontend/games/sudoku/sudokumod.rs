@@ -27,7 +27,7 @@ impl MySudoku {
         available
     }
 
-    /// Convert from a `[[u8; 9]; 9]` matrix (array-of-array) to a `Sudoku`
+    // Convert from a `[[u8; 9]; 9]` matrix (array-of-array) to a `Sudoku`
     // pub fn from_matrix(matrix: SudokuMatrix) -> Self {
     //     matrix.into()
     // }
@@ -1,6 +1,6 @@
 use std::{
     convert::TryInto,
-    io::Write,
+    io::{Read, Write},
     path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
@@ -11,13 +11,13 @@ use std::{
 };
 
 use crate::{
-    disks,
+    cache, checksum, disks, download,
     install::{self, log_system_info},
-    network, DEPLOYKIT_USER_AGENT, LOG_FILE,
+    network, LOG_FILE,
 };
 use anyhow::{anyhow, Result};
 use cursive::utils::Counter;
-use log::info;
+use log::{info, warn};
 // use nix::fcntl::FallocateFlags;
 use rand::{thread_rng, Rng};
 use rustix::{fd::AsFd, fs::FallocateFlags};
@@ -29,10 +29,15 @@ mod games;
 mod tui;
 
 pub use cli::*;
-use sha2::{Digest, Sha256};
 pub use tui::tui_main;
 
 pub const DEFAULT_EMPTY_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+pub const DEFAULT_PARALLEL_MIRRORS: usize = 4;
+// memory for data waiting to be hashed stays at CHECKSUM_BUFFERS * CHECKSUM_BUFFER_SIZE
+const CHECKSUM_BUFFERS: usize = 32;
+const CHECKSUM_BUFFER_SIZE: usize = 64 * 1024;
+// chunks of the system release waiting to be unpacked, each one is usually a few KiB
+const STREAM_QUEUE_LEN: usize = 256;
 
 const STEP1: &str = "Step 1 of 8: Formatting partitions";
 const STEP2: &str = "Step 2 of 8: Downloading system release";
@@ -58,8 +63,12 @@ macro_rules! send_error {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct InstallConfig {
     variant: Option<Arc<network::VariantEntry>>,
+    /// Without a path, the partition is created in the free space of its drive
+    /// once the installation starts
     partition: Option<Arc<disks::Partition>>,
     mirror: Option<Arc<network::Mirror>>,
+    fallback_mirrors: Option<Arc<Vec<network::Mirror>>>,
+    parallel_mirrors: Option<usize>,
     full_name: Option<Arc<String>>,
     user: Option<Arc<String>>,
     password: Option<Arc<String>>,
@@ -71,6 +80,31 @@ struct InstallConfig {
     use_swap: Arc<AtomicBoolWrapper>,
     swap_size: Arc<Option<f64>>,
     is_hibernation: Arc<AtomicBoolWrapper>,
+    #[serde(default)]
+    source: network::InstallSource,
+    #[serde(default)]
+    network: network::NetworkConfig,
+    #[serde(default)]
+    allow_unsigned_recipe: bool,
+    cache: Option<cache::DownloadCache>,
+    /// Install AOSC OS/Retro instead of mainline AOSC OS
+    #[serde(default = "network::detect_retro")]
+    retro: bool,
+    /// Unpack tarballs while downloading, instead of saving them to the target first
+    #[serde(default)]
+    stream_extract: bool,
+    /// Encrypt the system partition with LUKS2 using this passphrase, never saved to disk
+    #[serde(skip)]
+    luks_passphrase: Option<Arc<String>>,
+    /// Set in saved configs of encrypted installations, the passphrase has to be asked for again
+    #[serde(default)]
+    luks_passphrase_needed: bool,
+    /// Create an LVM volume group on the system partition and install onto its volumes
+    #[serde(default)]
+    lvm: Option<disks::LvmLayout>,
+    /// Partitions mounted next to the system partition, e.g. at /home or /var
+    #[serde(default)]
+    mount_points: Vec<disks::MountPoint>,
 }
 
 impl Default for InstallConfig {
@@ -79,6 +113,8 @@ impl Default for InstallConfig {
             variant: None,
             partition: None,
             mirror: None,
+            fallback_mirrors: None,
+            parallel_mirrors: None,
             full_name: None,
             user: None,
             password: None,
@@ -94,6 +130,16 @@ impl Default for InstallConfig {
                 v: AtomicBool::new(false),
             }),
             root_password: None,
+            source: network::InstallSource::default(),
+            network: network::NetworkConfig::default(),
+            allow_unsigned_recipe: false,
+            cache: None,
+            retro: network::detect_retro(),
+            stream_extract: false,
+            luks_passphrase: None,
+            luks_passphrase_needed: false,
+            lvm: None,
+            mount_points: vec![],
         }
     }
 }
@@ -167,13 +213,98 @@ fn begin_install(
     sender.send(InstallProgress::Pending(STEP1.to_string(), 0))?;
     info!("{}", STEP1);
 
-    let partition = &config.partition.unwrap();
+    if config.luks_passphrase_needed && config.luks_passphrase.is_none() {
+        return Err(anyhow!(
+            "The system partition is to be encrypted, but no LUKS passphrase was given."
+        ));
+    }
+    let partition = config.partition.unwrap();
+    let partition = match &partition.parent_path {
+        Some(dev) if partition.path.is_none() => {
+            info!(
+                "Creating system partition in the free space of {}",
+                dev.display()
+            );
+            let required_size = config.variant.as_ref().map_or(0, |x| x.required_size());
+            let created = disks::create_partition_in_free_space(dev, required_size)?;
+
+            Arc::new(disks::Partition {
+                fs_type: partition.fs_type.clone(),
+                ..created
+            })
+        }
+        _ => partition,
+    };
+    disks::check_mount_points(&partition, config.lvm.as_ref(), &config.mount_points)?;
+    let (partition, luks_uuid) = match &config.luks_passphrase {
+        Some(passphrase) => {
+            info!("Encrypting partition: {:?}", partition);
+            disks::luks_format(&partition, passphrase)?;
+            let uuid = disks::luks_uuid(&partition)?;
+            let mapped = disks::luks_open(&partition, passphrase)?;
+            info!("Installing onto encrypted partition: {:?}", mapped);
+
+            (Arc::new(mapped), Some(uuid))
+        }
+        None => (partition, None),
+    };
+    let use_swap = config.use_swap.v.load(Ordering::SeqCst);
+    let (partition, home_volume, swap_volume) = match &config.lvm {
+        Some(layout) => {
+            info!("Creating LVM volume group on: {:?}", partition);
+            let swap_size = if use_swap { *config.swap_size } else { None };
+            let volumes = disks::create_lvm(&partition, layout, swap_size.map(|x| x as u64))?;
+            info!("Installing onto logical volume: {:?}", volumes.root);
+
+            (Arc::new(volumes.root), volumes.home, volumes.swap)
+        }
+        None => (partition, None, None),
+    };
+    let partition = &partition;
+    // both existing and newly created logical volumes need to be activated at boot
+    let lvm_root = disks::lvm_volume_name(partition);
 
     info!("Formatting partitions: {:?}", partition);
     disks::format_partition(partition)?;
+    if partition.fs_type.as_deref() == Some("btrfs") {
+        disks::create_btrfs_subvolumes(partition.path.as_ref().unwrap())?;
+    }
+    for volume in home_volume.iter().chain(swap_volume.iter()) {
+        info!("Formatting logical volume: {:?}", volume);
+        disks::format_partition(volume)?;
+    }
 
     info!("Mounting partitions: {:?}", partition);
     let mount_path = install::auto_mount_root_path(&tempdir, partition)?;
+    if let Some(home_volume) = &home_volume {
+        info!("Mounting home volume: {:?}", home_volume);
+        let home_path = mount_path.join("home");
+        std::fs::create_dir_all(&home_path)?;
+        install::mount_partition(home_volume, &home_path)?;
+    }
+    let mut mount_points = config.mount_points.clone();
+    // parents first, e.g. /var before /var/log
+    mount_points.sort_by_key(|x| x.mount_path.components().count());
+    for mount_point in &mount_points {
+        if mount_point.format {
+            info!("Formatting partition: {:?}", mount_point.partition);
+            disks::format_partition(&mount_point.partition)?;
+        }
+        info!(
+            "Mounting partition {:?} at {}",
+            mount_point.partition.path,
+            mount_point.mount_path.display()
+        );
+        let target = mount_path.join(mount_point.mount_path.strip_prefix("/")?);
+        std::fs::create_dir_all(&target)?;
+        install::mount_partition(&mount_point.partition, &target)?;
+    }
+    // a failed unpack must not remove anything from partitions whose data is kept
+    let kept_mounts = mount_points
+        .iter()
+        .filter(|x| !x.format)
+        .map(|x| mount_path.join(x.mount_path.strip_prefix("/").unwrap_or(&x.mount_path)))
+        .collect::<Vec<_>>();
     let mount_path_copy = mount_path.clone();
     let mut efi_path = mount_path.clone();
     if disks::is_efi_booted() {
@@ -191,56 +322,143 @@ fn begin_install(
             info!("Formatting ESP partition: {:?}", esp_part);
             disks::format_partition(&esp_part)?;
         }
-        install::mount_root_path(&esp_part, &efi_path)?;
+        install::mount_partition(&esp_part, &efi_path)?;
     }
+    let local_rootfs;
+    let mut mirrors = vec![];
+    let variant_path;
     if let Some(variant) = config.variant.as_ref() {
-        let mirror_url = &config.mirror.as_ref().unwrap().url;
+        variant_path = variant.url.clone();
         file_size = variant.size.try_into().unwrap();
-        url = format!("{}{}", mirror_url, variant.url);
         right_sha256 = variant.sha256sum.clone();
+        local_rootfs = config.source.local_rootfs(variant).or_else(|| {
+            // a verified copy from an earlier install is as good as a local source
+            config
+                .cache
+                .as_ref()
+                .and_then(|x| x.lookup(&variant.sha256sum))
+        });
 
-        info!(
-            "Mirror URL is: {}, file_size: {}, url: {}, right_sha256: {}",
-            mirror_url, file_size, url, right_sha256
-        );
+        if let Some(local_rootfs) = &local_rootfs {
+            url = local_rootfs.to_string_lossy().to_string();
+            info!(
+                "Local system release is: {}, file_size: {}, right_sha256: {}",
+                url, file_size, right_sha256
+            );
+        } else {
+            let mirror = config.mirror.as_ref().unwrap();
+            url = format!("{}{}", mirror.url, variant.url);
+            info!(
+                "Mirror URL is: {}, file_size: {}, url: {}, right_sha256: {}",
+                mirror.url, file_size, url, right_sha256
+            );
+
+            mirrors.push(mirror.as_ref().clone());
+            for fallback in config.fallback_mirrors.iter().flat_map(|x| x.iter()) {
+                if mirrors.iter().all(|x| x.url != fallback.url) {
+                    mirrors.push(fallback.clone());
+                }
+            }
+            info!(
+                "Fallback mirrors: {:?}",
+                mirrors.iter().skip(1).map(|x| &x.name).collect::<Vec<_>>()
+            );
+        }
     } else {
         return Err(anyhow!(
             "Installer could not parse release metadata: `variant` field not found."
         ));
     }
 
-    let use_swap = config.use_swap.v.load(Ordering::SeqCst);
-    if use_swap {
+    let parallel_mirrors = config.parallel_mirrors;
+    let retro = config.retro;
+    let network = config.network.clone();
+    // squashfs images need random access, so only tarballs can be unpacked while downloading
+    let stream_extract = config.stream_extract
+        && local_rootfs.is_none()
+        && install::ExtractFileType::from_url(&variant_path)
+            != Some(install::ExtractFileType::Squashfs);
+    if config.stream_extract && !stream_extract {
+        info!("Not unpacking while downloading: system release is not a remote tarball");
+    }
+    // only freshly downloaded releases go into the cache
+    let cache = config
+        .cache
+        .clone()
+        .filter(|_| local_rootfs.is_none() && !stream_extract);
+    let keep_tarball = cache.is_some();
+    let variant_path_copy = variant_path.clone();
+    let right_sha256_copy = right_sha256.clone();
+    let tarball_file = mount_path.join("tarball");
+    let tarball_file_copy = tarball_file.clone();
+    if use_swap && swap_volume.is_none() {
         if let Some(swap_size) = config.swap_size.as_ref() {
             info!("Creating swapfile and trying swapon swapfile ...");
-            install::create_swapfile(*swap_size, use_swap, &tempdir)?;
+            let nocow = partition.fs_type.as_deref() == Some("btrfs");
+            install::create_swapfile(*swap_size, use_swap, &tempdir, nocow)?;
         }
     }
 
     let extract_done_copy = extract_done.clone();
     let download_done_copy = download_done.clone();
-    let (sha256_work_tx, sha256_work_rx) = mpsc::channel();
+    let (mut checksum_writer, checksum_worker) =
+        checksum::pipeline(CHECKSUM_BUFFERS, CHECKSUM_BUFFER_SIZE);
     let (get_sha256_tx, get_sha256_rx) = mpsc::channel();
-    let (error_channel_tx, error_channel_rx) = mpsc::channel();
-    let error_channel_tx_copy = error_channel_tx.clone();
+    let (error_channel_tx_copy, error_channel_rx) = mpsc::channel();
 
     let (speed_tx, speed_rx) = std::sync::mpsc::channel();
 
     let cc = counter.clone();
 
     let worker = thread::spawn(move || {
-        let mut tarball_file = mount_path.clone();
-        tarball_file.push("tarball");
-        let mut output = match std::fs::File::create(tarball_file.clone()) {
-            Ok(file) => {
-                info!("tarball file: {:?} is created", tarball_file);
+        if let Some(local_rootfs) = local_rootfs {
+            drop(speed_tx);
+            info!("Reading local system release: {:?}", &local_rootfs);
+            let mut input = match std::fs::File::open(&local_rootfs) {
+                Ok(file) => file,
+                Err(e) => {
+                    let e = anyhow!(
+                        "Installer failed to open local system release {}:\n\n{}",
+                        local_rootfs.display(),
+                        e
+                    );
+                    send_error!(error_channel_tx_copy, e);
+                }
+            };
 
-                file
+            let mut buf = vec![0; 1024 * 1024];
+            let mut read_size = 0;
+            loop {
+                match input.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        read_size += n;
+                        cc.set(read_size);
+                        if let Err(e) = checksum_writer.blocking_write(&buf[..n]) {
+                            send_error!(error_channel_tx_copy, e);
+                        }
+                    }
+                    Err(e) => {
+                        send_error!(error_channel_tx_copy, e);
+                    }
+                }
             }
-            Err(e) => {
+            drop(checksum_writer);
+            download_done_copy.fetch_or(true, Ordering::SeqCst);
+
+            info!("Trying extract local system release: {:?}", &local_rootfs);
+            cc.set(0);
+
+            if let Err(e) =
+                install::extract_file(file_size as f64, url, &local_rootfs, &mount_path, cc)
+            {
+                let e = anyhow!("Installer failed to unpack system release:\n\n{}", e);
                 send_error!(error_channel_tx_copy, e);
             }
-        };
+
+            extract_done_copy.fetch_or(true, Ordering::SeqCst);
+            return;
+        }
 
         let runtime = match tokio::runtime::Builder::new_multi_thread()
             .enable_all()
@@ -254,10 +472,7 @@ fn begin_install(
             }
         };
 
-        let client = match reqwest::Client::builder()
-            .user_agent(DEPLOYKIT_USER_AGENT!())
-            .build()
-        {
+        let client = match network.client() {
             Ok(c) => c,
             Err(e) => {
                 let e = anyhow!("Failed to create reqwest client: {e}");
@@ -265,7 +480,75 @@ fn begin_install(
             }
         };
 
-        let urlc = url.clone();
+        if stream_extract {
+            // the checksum is calculated by the stream reader instead
+            drop(checksum_writer);
+            info!("Unpacking system release while downloading it");
+            let (stream_tx, stream_rx) = tokio::sync::mpsc::channel(STREAM_QUEUE_LEN);
+            let mut unpacked = vec![];
+            let (downloaded, checksum) = thread::scope(|scope| {
+                let downloader = scope.spawn(|| {
+                    let stream_tx = stream_tx;
+                    runtime.block_on(download::download_stream(
+                        &client,
+                        &mirrors,
+                        &variant_path,
+                        file_size,
+                        &cc,
+                        &speed_tx,
+                        &stream_tx,
+                        network.rate_limit,
+                    ))
+                });
+
+                let mut reader = download::StreamReader::new(stream_rx);
+                let checksum = match install::extract_tar_stream(
+                    &mut reader,
+                    &url,
+                    &mount_path,
+                    &mut unpacked,
+                ) {
+                    Ok(()) => reader.finish(),
+                    Err(e) => {
+                        // stops the download as well
+                        drop(reader);
+                        Err(e)
+                    }
+                };
+
+                (downloader.join().unwrap(), checksum)
+            });
+
+            let e = match (downloaded, checksum) {
+                (Err(e), _) => e,
+                (Ok(()), Err(e)) => anyhow!("Installer failed to unpack system release:\n\n{}", e),
+                (Ok(()), Ok(checksum)) if checksum != right_sha256_copy => anyhow!(
+                    "Installer detected a checksum mismatch in downloaded system release.\n\nExpected hash: {}\n\nCalculated hash: {}",
+                    right_sha256_copy,
+                    checksum
+                ),
+                (Ok(()), Ok(_)) => {
+                    extract_done_copy.fetch_or(true, Ordering::SeqCst);
+                    download_done_copy.fetch_or(true, Ordering::SeqCst);
+                    return;
+                }
+            };
+
+            info!("Removing the unpacked system release");
+            install::remove_unpacked(unpacked, &kept_mounts);
+            send_error!(error_channel_tx_copy, e);
+        }
+
+        let mut output = match std::fs::File::create(tarball_file.clone()) {
+            Ok(file) => {
+                info!("tarball file: {:?} is created", tarball_file);
+
+                file
+            }
+            Err(e) => {
+                send_error!(error_channel_tx_copy, e);
+            }
+        };
 
         let tbl_file_c = tarball_file.clone();
 
@@ -274,13 +557,6 @@ fn begin_install(
         let error_channel_tx_copy_copy = error_channel_tx_copy.clone();
 
         runtime.block_on(async move {
-            let mut resp = match client.get(urlc).send().await.and_then(|x| x.error_for_status()) {
-                Ok(resp) => resp,
-                Err(e) => {
-                    send_error!(error_channel_tx_copy, e);
-                }
-            };
-
             info!("Allocating tarball file: {:?}", &tbl_file_c);
             if let Err(e) = rustix::fs::fallocate(
                 output.as_fd(),
@@ -301,54 +577,47 @@ fn begin_install(
                 send_error!(error_channel_tx_copy, e);
             }
 
-            let mut tarball_size = 0;
-
-            let mut timer = tokio::time::Instant::now();
-            let mut tarball_size_1s = 0;
-
-            loop {
-                if tarball_size == file_size {
-                    info!("Download complete");
-                    download_done_copy.fetch_or(true, Ordering::SeqCst);
-                    break;
-                }
-                match resp.chunk().await {
-                    Ok(v) => {
-                        if let Some(chunk) = v {
-                            let now = timer.elapsed().as_secs_f64();
-                            if now >= 1.0 {
-                                let speed = tarball_size_1s as f64 / 1024.0 / now;
-                                let eta = (file_size - tarball_size) as f64 / 1024.0 / speed;
-                                let (s, s2) = calc_speed(speed, eta);
-                                speed_tx.send((s, s2)).unwrap();
-                                tarball_size_1s = 0;
-                                timer = tokio::time::Instant::now();
-                            } else {
-                                tarball_size_1s += chunk.len();
-                            }
-
-                            if let Err(e) = output.write_all(&chunk) {
-                                send_error!(error_channel_tx_copy, e);
-                            }
-                            tarball_size += chunk.len();
-                            cc.set(tarball_size);
-                            sha256_work_tx.send((chunk.to_vec(), chunk.len())).unwrap();
-                        } else {
-                            let e = "The remote closed the connection prematurely.".to_string();
-                            send_error!(error_channel_tx_copy, e);
-                        }
-                    }
-                    Err(e) => {
-                         send_error!(error_channel_tx_copy, e);
-                    }
+            let res = match parallel_mirrors {
+                Some(segments) if segments > 1 => {
+                    info!("Downloading from up to {} mirrors in parallel", segments);
+                    download::download_file_segmented(
+                        &client,
+                        &mirrors,
+                        &variant_path,
+                        &output,
+                        file_size,
+                        &cc,
+                        &speed_tx,
+                        &mut checksum_writer,
+                        segments,
+                        network.rate_limit,
+                        retro,
+                    )
+                    .await
                 }
-                if cfg!(feature = "is_retro") {
-                    // 确保在 Retro 设备上不会因为网速过快，数据来不及写入硬盘导致的 OOM
-                    if let Err(e) = output.flush() {
-                        send_error!(error_channel_tx_copy, e);
-                    }
+                _ => {
+                    download::download_file(
+                        &client,
+                        &mirrors,
+                        &variant_path,
+                        &output,
+                        file_size,
+                        &cc,
+                        &speed_tx,
+                        &mut checksum_writer,
+                        network.rate_limit,
+                        retro,
+                    )
+                    .await
                 }
+            };
+
+            if let Err(e) = res {
+                send_error!(error_channel_tx_copy, e);
             }
+
+            info!("Download complete");
+            download_done_copy.fetch_or(true, Ordering::SeqCst);
         });
 
         info!("Trying extract tarball file: {:?}", &tarball_file);
@@ -364,29 +633,14 @@ fn begin_install(
 
         extract_done_copy.fetch_or(true, Ordering::SeqCst);
 
-        info!("Trying remove tarball file: {:?}", tarball_file);
-        std::fs::remove_file(tarball_file).ok();
+        if !keep_tarball {
+            info!("Trying remove tarball file: {:?}", tarball_file);
+            std::fs::remove_file(tarball_file).ok();
+        }
     });
 
     let sha256sum_work = thread::spawn(move || {
-        let mut hasher = Sha256::new();
-        loop {
-            let rx = if let Ok(result) = sha256_work_rx.recv() {
-                result
-            } else {
-                // dbg!("sha256sum complete");
-                get_sha256_tx.send(hasher).unwrap();
-                return;
-            };
-            let (buf, reader_size) = rx;
-            if let Err(e) = hasher.write_all(&buf[..reader_size]) {
-                let e = anyhow!(
-                    "Installer failed to calculate checksum for system release:\n\n{}",
-                    e
-                );
-                send_error!(error_channel_tx, e);
-            }
-        }
+        get_sha256_tx.send(checksum_worker.run()).unwrap();
     });
 
     let file_size = file_size as f64;
@@ -413,26 +667,28 @@ fn begin_install(
             break;
         }
     }
-    let mut fake_counter = 0;
+    // streamed releases are verified by the worker before it reports the download as done
+    if !stream_extract {
+        let mut fake_counter = 0;
 
-    info!("{}", STEP3);
-    loop {
-        sender.send(InstallProgress::Pending(STEP3.to_string(), fake_counter))?;
-        std::thread::sleep(refresh_interval);
-        if let Ok(hasher) = get_sha256_rx.try_recv() {
-            let final_hash = hex::encode(hasher.finalize());
-            if final_hash != right_sha256 {
-                return Err(anyhow!(
-                    "Installer detected a checksum mismatch in downloaded system release.\n\nExpected hash: {}\n\nCalculated hash: {}",
-                    right_sha256,
-                    final_hash
-                ));
+        info!("{}", STEP3);
+        loop {
+            sender.send(InstallProgress::Pending(STEP3.to_string(), fake_counter))?;
+            std::thread::sleep(refresh_interval);
+            if let Ok(final_hash) = get_sha256_rx.try_recv() {
+                if final_hash != right_sha256 {
+                    return Err(anyhow!(
+                        "Installer detected a checksum mismatch in downloaded system release.\n\nExpected hash: {}\n\nCalculated hash: {}",
+                        right_sha256,
+                        final_hash
+                    ));
+                }
+                break;
+            }
+            fake_counter += 1;
+            if fake_counter == 100 {
+                fake_counter = 0;
             }
-            break;
-        }
-        fake_counter += 1;
-        if fake_counter == 100 {
-            fake_counter = 0;
         }
     }
 
@@ -450,9 +706,25 @@ fn begin_install(
     // GC the worker thread
     worker.join().unwrap();
     sha256sum_work.join().unwrap();
+
+    if let Some(cache) = cache {
+        // the checksum has been verified above
+        info!("Saving system release to cache: {:?}", cache.dir);
+        if let Err(e) = cache.store(&tarball_file_copy, &right_sha256, &variant_path_copy) {
+            warn!("Installer failed to cache system release: {}", e);
+        }
+        info!("Trying remove tarball file: {:?}", tarball_file_copy);
+        std::fs::remove_file(&tarball_file_copy).ok();
+    }
     // genfstab to file
     info!("Generating fstab ...");
     install::genfstab_to_file(partition, &tempdir, Path::new("/"))?;
+    if let Some(home_volume) = &home_volume {
+        install::genfstab_to_file(home_volume, &tempdir, Path::new("/home"))?;
+    }
+    for mount_point in &mount_points {
+        install::genfstab_to_file(&mount_point.partition, &tempdir, &mount_point.mount_path)?;
+    }
 
     if disks::is_efi_booted() {
         info!("Generating fstab efi entry...");
@@ -469,8 +741,21 @@ fn begin_install(
     let escape_vector = install::get_dir_fd(Path::new("/"))?;
     install::dive_into_guest(&mount_path_copy)?;
 
+    if let Some(root_volume) = &lvm_root {
+        info!(
+            "Setting up activation of logical volume {} ...",
+            root_volume
+        );
+        install::setup_lvm(root_volume)?;
+    }
+
+    if let Some(uuid) = &luks_uuid {
+        info!("Setting up unlocking of LUKS container {} ...", uuid);
+        install::setup_luks(uuid)?;
+    }
+
     info!("Running dracut ...");
-    install::execute_dracut()?;
+    install::execute_dracut(retro)?;
 
     let fake_counter: usize = rng.gen_range(0..100);
     sender.send(InstallProgress::Pending(STEP6.to_string(), fake_counter))?;
@@ -489,7 +774,7 @@ fn begin_install(
     info!("{}", STEP7);
 
     info!("Generating SSH key ...");
-    install::gen_ssh_key()?;
+    install::gen_ssh_key(retro)?;
 
     info!("{}", STEP8);
     let fake_counter: usize = rng.gen_range(0..100);
@@ -497,7 +782,11 @@ fn begin_install(
 
     if use_swap {
         info!("Generating swapfile entry to fstab");
-        install::write_swap_entry_to_fstab()?;
+        let swap = swap_volume
+            .as_ref()
+            .and_then(|x| x.path.as_deref())
+            .unwrap_or(Path::new("/swapfile"));
+        install::write_swap_entry_to_fstab(swap)?;
     }
 
     let tz = config.timezone.unwrap();
@@ -520,13 +809,13 @@ fn begin_install(
 
     install::add_new_user(&config.user.clone().unwrap(), &config.password.unwrap())?;
 
-    if cfg!(feature = "is_retro") {
+    if retro {
         install::chpasswd("root", &config.root_password.unwrap())?;
     }
 
     info!("Setting fullname ...");
-    if config.full_name.is_some() && config.full_name != Some("".to_string().into()) {
-        install::passwd_set_fullname(&config.full_name.unwrap(), &config.user.unwrap())?;
+    if let Some(full_name) = config.full_name.filter(|x| !x.is_empty()) {
+        install::passwd_set_fullname(&full_name, &config.user.unwrap())?;
     }
 
     let locale = config.locale.as_ref().unwrap();
@@ -561,24 +850,29 @@ fn begin_install(
     info!("Unmounting main partition ...");
     install::umount_root_path(&mount_path_copy).ok();
 
+    if config.lvm.is_some() {
+        info!("Deactivating LVM volume group ...");
+        disks::lvm_close();
+    }
+
+    if luks_uuid.is_some() {
+        info!("Closing encrypted partition ...");
+        disks::luks_close();
+    }
+
     sender.send(InstallProgress::Finished)?;
 
     Ok(())
 }
 
-fn calc_speed(speed: f64, eta: f64) -> (String, String) {
-    let s = match speed {
-        x if x > 1000.0 * 1000.0 => format!("{:.1}GiB/s", speed / 1024.0 / 1024.0),
-        x if x > 1000.0 => format!("{:.1}MiB/s", speed / 1024.0),
-        x => format!("{x:.1}KiB/s"),
+#[test]
+fn test_luks_passphrase_not_serialized() {
+    let config = InstallConfig {
+        luks_passphrase: Some(Arc::new("hunter2".to_string())),
+        ..Default::default()
     };
-
-    let s2 = match eta {
-        x if x >= 60.0 * 60.0 * 24.0 => format!("{:.1}d", (x / 60.0 / 60.0 / 24.0).round()),
-        x if x >= 60.0 * 60.0 => format!("{:.1}h", (x / 60.0 / 60.0).round()),
-        x if x >= 60.0 => format!("{:.1}m", (eta / 60.0).round()),
-        x => format!("{:.0}s", x.round()),
-    };
-
-    (s, s2)
+    let json = serde_json::to_string(&config).unwrap();
+    assert!(!json.contains("hunter2"));
+    let config: InstallConfig = serde_json::from_str(&json).unwrap();
+    assert!(config.luks_passphrase.is_none());
 }
@@ -3,16 +3,20 @@ use crate::{
         self, auto_create_partitions, device_is_empty, is_efi_booted, DkDerive, ALLOWED_FS_TYPE,
     },
     install::{self, find_language_by_locale, find_locale_by_language, read_locale, umount_all},
-    network::{self, Mirror, VariantEntry},
+    network::{
+        self, Bulletin, InstallSource, Mirror, MirrorBenchmark, NetworkConfig, VariantEntry,
+    },
     LOG_FILE,
 };
 use anyhow::Result;
 use cursive::{
     event::Event,
+    theme::BaseColor,
+    utils::markup::StyledString,
     view::Selector,
     views::{
-        Dialog, DummyView, EditView, LinearLayout, ListView, NamedView, Panel, ProgressBar,
-        RadioGroup, ResizedView, ScrollView, SelectView, TextContent, TextView,
+        Checkbox, Dialog, DummyView, EditView, LinearLayout, ListView, NamedView, Panel,
+        ProgressBar, RadioGroup, ResizedView, ScrollView, SelectView, TextContent, TextView,
     },
 };
 use cursive::{traits::*, utils::Counter};
@@ -34,6 +38,7 @@ use std::{
 
 use super::{
     begin_install, games::add_main_callback, AtomicBoolWrapper, InstallConfig, DEFAULT_EMPTY_SIZE,
+    DEFAULT_PARALLEL_MIRRORS,
 };
 
 const LAST_USER_CONFIG_FILE: &str = "/tmp/deploykit-config.json";
@@ -87,6 +92,7 @@ macro_rules! SURE_FS_FORMAT_INFO {
     };
 }
 
+const BTRFS_LAYOUT_INFO: &str = "\nBtrfs partitions are set up with separate @ (/) and @home (/home) subvolumes, and transparent zstd compression.";
 const ADVANCED_METHOD_INFO: &str = "Installer detected an unsupported filesystem format in your system partition. If you proceed, the installer will format your system partition using the ext4 filesystem. Please refer to the manual installation guides if you prefer to use an unsupported filesystem.";
 const WELCOME_TEXT: &str = r#"Welcome to the AOSC OS Installer!
 
@@ -99,7 +105,11 @@ const ENTER_USER_PASSWORD_TEXT: &str = r#"Please enter and confirm your desired
 "#;
 const ENTER_HOSTNAME_TEXT: &str = r#"Now, please input your desired hostname. A hostname may only consist letters a-z, numbers 0-9, and dash ("-")."#;
 const ENTER_TIMEZONE_TEXT: &str = r#"Finally, please select your locale, timezone, and your clock preferences. Your locale setting will affect your installation's display language. UTC system time is the default setting for Linux systems, but may result in time discrepancy with your other operating systems, such as Windows. If you wish to prevent this from happening, please select local time as system time."#;
-const BENCHMARK_TEXT: &str = "Installer will now test all mirrors for download speed, and rank them from the fastest (top) to the slowest (bottom). This may take a few minutes.";
+const MOUNT_POINTS_TEXT: &str = "If you would like to keep data such as /home on other partitions, you may mount them in AOSC OS as well. Each partition is either formatted or kept as it is, along with its data.";
+const RELEASE_DATE_TEXT: &str = "Please select the release you would like to install. The newest release is marked with an asterisk (*); older releases are kept in case a newer one does not work on your device.";
+const NETWORK_TEXT: &str = "If you need a proxy to access the Internet, please enter its URL below (e.g., http://proxy:3128 or socks5h://127.0.0.1:1080). Leave it empty to use the http_proxy, https_proxy and all_proxy environment variables, if set.\n\nIf your mirror uses a private certificate authority, enter the paths to its PEM CA bundles, separated by commas.\n\nTo avoid saturating a shared uplink, you may limit the download speed of the system release. Leave it empty for no limit.";
+const SOURCE_TEXT: &str = "Please enter the URL to a release recipe (recipe.json), or the path (or file:// URL) to a local directory containing recipe.json and system releases, laid out the same way as a mirror.";
+const BENCHMARK_TEXT: &str = "Installer will now test all mirrors for latency and download speed, and rank them from the fastest (top) to the slowest (bottom). This may take a few minutes.";
 const FINISHED_TEXT: &str = r#"AOSC OS has been successfully installed on your device.
 
 You may reboot to your installed system by choosing "Reboot," or return to LiveKit by selecting "Exit to LiveKit.""#;
@@ -267,6 +277,12 @@ fn build_variant_list(
     config: InstallConfig,
 ) -> Dialog {
     let mut config_view = LinearLayout::vertical();
+    let config_clone = config.clone();
+    let config_clone_2 = config.clone();
+    let config_clone_3 = config.clone();
+    let mirrors_clone = mirrors.clone();
+    // the table only lists the newest release of every variant
+    let latest = network::latest_variants(&variants);
 
     let variant_view = TableView::<network::VariantEntry, VariantColumn>::new()
         .column(VariantColumn::Name, "Available Distributions", |c| {
@@ -274,45 +290,463 @@ fn build_variant_list(
         })
         .column(VariantColumn::Date, "Last Updated", |c| c.width(22))
         .column(VariantColumn::Size, "Download Size", |c| c.width(22))
-        .items(variants.clone())
+        .items(latest.clone())
         .on_submit(move |siv, _row, index| {
-            let mut config = config.clone();
-            config.variant = Some(Arc::new(variants.get(index).unwrap().clone()));
-            select_mirrors(siv, mirrors.clone(), config);
+            let mut config = config_clone.clone();
+            config.variant = Some(Arc::new(latest.get(index).unwrap().clone()));
+            variant_selected(siv, mirrors.clone(), config);
         })
+        .with_name("variant_list")
         .min_width(80)
         .min_height(30);
     let variant_view = Panel::new(variant_view).title("Variant");
     config_view.add_child(TextView::new(VARIANT_TEXT));
+    if config.source != InstallSource::default() {
+        config_view.add_child(TextView::new(format!(
+            "Using source: {}",
+            config.source.location()
+        )));
+    }
+    if config.retro {
+        config_view.add_child(TextView::new("Showing AOSC OS/Retro variants."));
+    }
     config_view.add_child(variant_view);
     config_view.add_child(DummyView {});
 
-    wrap_in_dialog(config_view, "AOSC OS Installation", Some(128)).button("Exit", |s| s.quit())
+    wrap_in_dialog(config_view, "AOSC OS Installation", Some(128))
+        .button("Older Releases", move |s| {
+            let selected = s.call_on_name(
+                "variant_list",
+                |v: &mut TableView<VariantEntry, VariantColumn>| {
+                    v.item()
+                        .and_then(|i| v.borrow_item(i).map(|x| x.name.clone()))
+                },
+            );
+            if let Some(Some(name)) = selected {
+                select_release_date(
+                    s,
+                    &name,
+                    &variants,
+                    mirrors_clone.clone(),
+                    config_clone_2.clone(),
+                );
+            }
+        })
+        .button("Change Source", move |s| {
+            select_source(s, config_clone_3.clone())
+        })
+        .button(
+            if config.retro {
+                "Show Mainline"
+            } else {
+                "Show Retro"
+            },
+            move |s| {
+                let mut config = config.clone();
+                config.retro = !config.retro;
+                select_variant(s, config);
+            },
+        )
+        .button("Exit", |s| s.quit())
+}
+
+fn variant_selected(siv: &mut Cursive, mirrors: Vec<Mirror>, config: InstallConfig) {
+    if config.source.is_remote() {
+        select_mirrors(siv, mirrors, config);
+    } else if config.partition.is_some() {
+        siv.pop_layer();
+        siv.add_layer(select_user_password(config));
+    } else {
+        select_disk(siv, config);
+    }
+}
+
+fn select_release_date(
+    siv: &mut Cursive,
+    name: &str,
+    variants: &[VariantEntry],
+    mirrors: Vec<Mirror>,
+    config: InstallConfig,
+) {
+    let releases = variants
+        .iter()
+        .filter(|x| x.name == name)
+        .enumerate()
+        .map(|(i, x)| {
+            let label = format!(
+                "{}{:<14}{}",
+                if i == 0 { "* " } else { "  " },
+                x.date,
+                human_size(x.size)
+            );
+            (label, x.clone())
+        });
+    let release_view =
+        SelectView::new()
+            .with_all(releases)
+            .on_submit(move |s, variant: &VariantEntry| {
+                let mut config = config.clone();
+                config.variant = Some(Arc::new(variant.clone()));
+                // pop the release list, the variant list is replaced by the next step
+                s.pop_layer();
+                variant_selected(s, mirrors.clone(), config);
+            });
+
+    siv.add_layer(
+        wrap_in_dialog(
+            LinearLayout::vertical()
+                .child(TextView::new(RELEASE_DATE_TEXT))
+                .child(DummyView {})
+                .child(release_view),
+            format!("Releases of {name}"),
+            None,
+        )
+        .button("Cancel", |s| {
+            s.pop_layer();
+        }),
+    );
+}
+
+fn select_source(siv: &mut Cursive, config: InstallConfig) {
+    let location = Rc::new(RefCell::new(config.source.location()));
+    let location_copy = location.clone();
+    let config_clone = config.clone();
+    siv.add_layer(
+        Dialog::around(
+            LinearLayout::vertical()
+                .child(TextView::new(SOURCE_TEXT).max_width(80))
+                .child(DummyView {})
+                .child(
+                    EditView::new()
+                        .content(config.source.location())
+                        .on_edit_mut(move |_, c, _| {
+                            location_copy.replace(c.to_owned());
+                        })
+                        .min_width(40),
+                )
+                .child(
+                    LinearLayout::horizontal()
+                        .child(
+                            Checkbox::new()
+                                .with_checked(config.allow_unsigned_recipe)
+                                .with_name("allow_unsigned_recipe"),
+                        )
+                        .child(TextView::new(
+                            " Do not verify the recipe signature (insecure)",
+                        )),
+                ),
+        )
+        .title("Specify installation source")
+        .button("Continue", move |s| {
+            let location = location.as_ref().to_owned().into_inner();
+            let allow_unsigned_recipe = s
+                .call_on_name("allow_unsigned_recipe", |v: &mut Checkbox| v.is_checked())
+                .unwrap_or(false);
+            match InstallSource::from_location(location.trim()) {
+                Ok(source) => {
+                    let mut config = config.clone();
+                    config.source = source;
+                    config.allow_unsigned_recipe = allow_unsigned_recipe;
+                    // pop input window, select_variant will replace the variant list
+                    s.pop_layer();
+                    select_variant(s, config);
+                }
+                Err(e) => show_msg(s, &e.to_string()),
+            }
+        })
+        .button("Reset", move |s| {
+            let mut config = config_clone.clone();
+            config.source = InstallSource::default();
+            config.allow_unsigned_recipe = false;
+            s.pop_layer();
+            select_variant(s, config);
+        })
+        .button("Cancel", |s| {
+            s.pop_layer();
+        })
+        .padding_lrtb(2, 2, 1, 1),
+    );
+}
+
+fn select_network(siv: &mut Cursive, network: Rc<RefCell<NetworkConfig>>) {
+    let proxy = Rc::new(RefCell::new(
+        network.borrow().proxy.clone().unwrap_or_default(),
+    ));
+    let proxy_copy = proxy.clone();
+    let ca_certs = Rc::new(RefCell::new(
+        network
+            .borrow()
+            .ca_certs
+            .iter()
+            .map(|x| x.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+    ));
+    let ca_certs_copy = ca_certs.clone();
+    let system_ca = network.borrow().system_ca;
+    let rate_limit = Rc::new(RefCell::new(
+        network
+            .borrow()
+            .rate_limit
+            .map(|x| format!("{}", x as f64 / 1024.0 / 1024.0))
+            .unwrap_or_default(),
+    ));
+    let rate_limit_copy = rate_limit.clone();
+    siv.add_layer(
+        Dialog::around(
+            LinearLayout::vertical()
+                .child(TextView::new(NETWORK_TEXT).max_width(80))
+                .child(DummyView {})
+                .child(
+                    ListView::new()
+                        .child(
+                            "Proxy",
+                            EditView::new()
+                                .content(proxy.as_ref().to_owned().into_inner())
+                                .on_edit_mut(move |_, c, _| {
+                                    proxy_copy.replace(c.to_owned());
+                                })
+                                .min_width(40),
+                        )
+                        .child(
+                            "CA certificates",
+                            EditView::new()
+                                .content(ca_certs.as_ref().to_owned().into_inner())
+                                .on_edit_mut(move |_, c, _| {
+                                    ca_certs_copy.replace(c.to_owned());
+                                })
+                                .min_width(40),
+                        )
+                        .child(
+                            "Speed limit (MiB/s)",
+                            EditView::new()
+                                .content(rate_limit.as_ref().to_owned().into_inner())
+                                .on_edit_mut(move |_, c, _| {
+                                    rate_limit_copy.replace(c.to_owned());
+                                })
+                                .min_width(40),
+                        ),
+                )
+                .child(
+                    LinearLayout::horizontal()
+                        .child(
+                            Checkbox::new()
+                                .with_checked(system_ca)
+                                .with_name("system_ca"),
+                        )
+                        .child(TextView::new(
+                            " Trust the CA certificates of the live system",
+                        )),
+                ),
+        )
+        .title("Network Settings")
+        .button("Continue", move |s| {
+            let proxy = proxy.as_ref().to_owned().into_inner();
+            let ca_certs = ca_certs
+                .borrow()
+                .split(',')
+                .map(|x| x.trim())
+                .filter(|x| !x.is_empty())
+                .map(PathBuf::from)
+                .collect::<Vec<_>>();
+            let system_ca = s
+                .call_on_name("system_ca", |v: &mut Checkbox| v.is_checked())
+                .unwrap_or(false);
+            let rate_limit = rate_limit.borrow().trim().to_string();
+            let rate_limit = if rate_limit.is_empty() {
+                None
+            } else if let Ok(rate_limit) = rate_limit.parse::<f64>() {
+                Some(rate_limit)
+            } else {
+                show_msg(
+                    s,
+                    "Please enter the speed limit as a number, or leave it empty.",
+                );
+                return;
+            };
+            match NetworkConfig::new(Some(&proxy))
+                .and_then(|x| x.with_ca_certs(ca_certs, system_ca))
+                .and_then(|x| x.with_rate_limit(rate_limit))
+            {
+                Ok(config) => {
+                    network.replace(config);
+                    s.pop_layer();
+                }
+                Err(e) => show_msg(s, &e.to_string()),
+            }
+        })
+        .button("Cancel", |s| {
+            s.pop_layer();
+        })
+        .padding_lrtb(2, 2, 1, 1),
+    );
 }
 
 fn select_variant(siv: &mut Cursive, config: InstallConfig) {
     siv.pop_layer();
+    let source = config.source.clone();
+    let network = config.network.clone();
+    let allow_unsigned_recipe = config.allow_unsigned_recipe;
+    let retro = config.retro;
     let loader = AsyncView::new_with_bg_creator(
         siv,
         move || {
-            let manifest = network::fetch_recipe().map_err(|e| e.to_string())?;
-            let mirrors = network::fetch_mirrors(&manifest);
-            let variants = network::find_variant_candidates(manifest).map_err(|e| e.to_string())?;
-            Ok((mirrors, variants))
+            // errors are shown in a dialog of our own, to offer another source from there
+            Ok(
+                network::fetch_recipe(&source, &network, allow_unsigned_recipe).and_then(
+                    |manifest| {
+                        let mirrors = network::fetch_mirrors(&manifest);
+                        let bulletin = manifest.bulletin.clone();
+                        let variants = network::find_variant_candidates(manifest, retro)?;
+                        Ok((bulletin, mirrors, variants))
+                    },
+                ),
+            )
+        },
+        move |res| {
+            let (bulletin, mirrors, variants) = match res {
+                Ok(res) => res,
+                Err(e) => return recipe_error_dialog(&e.to_string(), config.clone()),
+            };
+            if bulletin.is_empty() {
+                return build_variant_list(mirrors, variants, config.clone());
+            }
+            let config = config.clone();
+            show_bulletin(&bulletin).button("Continue", move |s| {
+                s.pop_layer();
+                s.add_layer(build_variant_list(
+                    mirrors.clone(),
+                    variants.clone(),
+                    config.clone(),
+                ));
+            })
         },
-        move |(mirrors, variants)| build_variant_list(mirrors, variants, config.clone()),
     );
 
     siv.add_layer(loader);
 }
 
+/// Explain why the recipe could not be loaded, with a way to pick another source or to
+/// skip the signature check from "Change Source"
+fn recipe_error_dialog(error: &str, config: InstallConfig) -> Dialog {
+    let config_clone = config.clone();
+
+    wrap_in_dialog(
+        TextView::new(format!(
+            "Installer could not load the release recipe:\n\n{error}"
+        )),
+        "AOSC OS Installer",
+        Some(80),
+    )
+    .button("Retry", move |s| {
+        select_variant(s, config.clone());
+    })
+    .button("Change Source", move |s| {
+        select_source(s, config_clone.clone());
+    })
+    .button("Exit", |s| s.quit())
+}
+
+/// Show the notice left by release engineers in the recipe (e.g. known issues)
+fn show_bulletin(bulletin: &Bulletin) -> Dialog {
+    let (title, color) = match bulletin.type_.as_str() {
+        "warning" => ("Warning", Some(BaseColor::Yellow)),
+        "error" => ("Important", Some(BaseColor::Red)),
+        _ => ("Notice", None),
+    };
+    let title = if bulletin.title().is_empty() {
+        title.to_string()
+    } else {
+        format!("{}: {}", title, bulletin.title())
+    };
+    let body = match color {
+        Some(color) => StyledString::styled(bulletin.body(), color.light()),
+        None => StyledString::plain(bulletin.body()),
+    };
+
+    wrap_in_dialog(TextView::new(body), title, Some(80))
+}
+
 fn select_mirrors(siv: &mut Cursive, mirrors: Vec<Mirror>, config: InstallConfig) {
     siv.pop_layer();
-    let (config_view, repo_list) = select_mirror_view_base(&mirrors);
-    siv.add_layer(select_mirrors_view(config_view, config, repo_list, mirrors));
+    let (config_view, repo_list) = select_mirror_view_base(&mirrors, &[], &config);
+    siv.add_layer(select_mirrors_view(
+        config_view,
+        config,
+        repo_list,
+        mirrors,
+        false,
+    ));
+}
+
+fn benchmark_mirrors(siv: &mut Cursive, mirrors: Vec<Mirror>, config: InstallConfig) {
+    let total = mirrors.len();
+    let status = TextContent::new(format!("Benchmarking {total} mirrors ...\n\n"));
+    siv.add_layer(wrap_in_dialog(
+        TextView::new_with_content(status.clone()),
+        "Benchmarking Mirrors",
+        None,
+    ));
+
+    let cb_sink = siv.cb_sink().clone();
+    thread::spawn(move || {
+        let finished = std::cell::Cell::new(0);
+        let results = network::benchmark_mirrors(mirrors.clone(), &config.network, |x| {
+            finished.set(finished.get() + 1);
+            let score = match &x.score {
+                Ok(score) => score.to_string(),
+                Err(_) => "unreachable".to_string(),
+            };
+            status.append(format!(
+                "[{}/{}] {}: {}\n",
+                finished.get(),
+                total,
+                x.mirror.name,
+                score
+            ));
+            // redraw with the new result
+            cb_sink.send(Box::new(|_| {})).ok();
+        });
+
+        cb_sink
+            .send(Box::new(move |s| {
+                s.pop_layer();
+                match results {
+                    Ok(results) => {
+                        let mirrors = results.iter().map(|x| x.mirror.clone()).collect::<Vec<_>>();
+                        let (config_view, repo_list) =
+                            select_mirror_view_base(&mirrors, &results, &config);
+                        s.add_layer(select_mirrors_view(
+                            config_view,
+                            config,
+                            repo_list,
+                            mirrors,
+                            true,
+                        ));
+                    }
+                    Err(e) => {
+                        let (config_view, repo_list) =
+                            select_mirror_view_base(&mirrors, &[], &config);
+                        s.add_layer(select_mirrors_view(
+                            config_view,
+                            config,
+                            repo_list,
+                            mirrors,
+                            false,
+                        ));
+                        show_msg(s, &format!("Installer failed to benchmark mirrors:\n\n{e}"));
+                    }
+                }
+            }))
+            .ok();
+    });
 }
 
-fn select_mirror_view_base(mirrors: &[Mirror]) -> (LinearLayout, RadioGroup<Mirror>) {
+fn select_mirror_view_base(
+    mirrors: &[Mirror],
+    results: &[MirrorBenchmark],
+    config: &InstallConfig,
+) -> (LinearLayout, RadioGroup<Mirror>) {
     let mut config_view = LinearLayout::vertical();
     let mut repo_list = RadioGroup::new();
     let mirror_list = mirrors;
@@ -322,11 +756,45 @@ fn select_mirror_view_base(mirrors: &[Mirror]) -> (LinearLayout, RadioGroup<Mirr
         ))
         .child(DummyView {});
     for mirror in mirror_list {
-        let radio = repo_list.button(mirror.clone(), format!("{} ({})", mirror.name, mirror.loc));
+        let score = match results.iter().find(|x| x.mirror.url == mirror.url) {
+            Some(MirrorBenchmark {
+                score: Ok(score), ..
+            }) => format!(" - {score}"),
+            Some(MirrorBenchmark { score: Err(_), .. }) => " - unreachable".to_string(),
+            None => String::new(),
+        };
+        let radio = repo_list.button(
+            mirror.clone(),
+            format!("{} ({}){}", mirror.name, mirror.loc, score),
+        );
         repo_view.add_child(radio);
     }
     let repo_view = Panel::new(repo_view).title("Mirrors");
     config_view.add_child(repo_view);
+    config_view.add_child(
+        LinearLayout::horizontal()
+            .child(Checkbox::new().with_name("parallel_mirrors"))
+            .child(TextView::new(format!(
+                " Download from the top {DEFAULT_PARALLEL_MIRRORS} mirrors in parallel"
+            ))),
+    );
+    // only tarballs can be unpacked while downloading
+    if config.variant.as_ref().is_some_and(|x| {
+        install::ExtractFileType::from_url(&x.url) != Some(install::ExtractFileType::Squashfs)
+    }) {
+        // saves disk space and I/O on slow Retro devices
+        config_view.add_child(
+            LinearLayout::horizontal()
+                .child(
+                    Checkbox::new()
+                        .with_checked(config.stream_extract || config.retro)
+                        .with_name("stream_extract"),
+                )
+                .child(TextView::new(
+                    " Unpack while downloading, without saving a copy first",
+                )),
+        );
+    }
     config_view.add_child(DummyView {});
 
     (config_view, repo_list)
@@ -337,21 +805,36 @@ fn select_mirrors_view(
     config: InstallConfig,
     repo_list: RadioGroup<Mirror>,
     mirrors: Vec<Mirror>,
+    ranked: bool,
 ) -> Dialog {
     let config_clone = config.clone();
     let config_clone_2 = config.clone();
     let config_clone_3 = config.clone();
+    let fallback_mirrors = Arc::new(mirrors.clone());
+    let fallback_mirrors_clone = fallback_mirrors.clone();
     wrap_in_dialog(config_view, "AOSC OS Installation", None)
         .button("Continue", move |s| {
             let mut config = config.clone();
             let mirror = repo_list.selection();
             config.mirror = Some(Arc::new(Rc::as_ref(&mirror).clone()));
-            if config.partition.is_some() {
-                s.pop_layer();
-                s.add_layer(select_user_password(config));
-            } else {
-                select_disk(s, config);
-            }
+            // the other mirrors, in the (benchmarked) order shown to the user
+            config.fallback_mirrors = Some(Arc::new(
+                fallback_mirrors
+                    .iter()
+                    .filter(|x| x.url != mirror.url)
+                    .cloned()
+                    .collect(),
+            ));
+            config.parallel_mirrors = parallel_mirrors(s);
+            config.stream_extract = stream_extract(s);
+            rank_parallel_mirrors(s, config, ranked, |s, config| {
+                if config.partition.is_some() {
+                    s.pop_layer();
+                    s.add_layer(select_user_password(config));
+                } else {
+                    select_disk(s, config);
+                }
+            });
         })
         .button("Benchmark Mirrors", move |s| {
             let config_clone_2 = config_clone.clone();
@@ -363,26 +846,8 @@ fn select_mirrors_view(
                 Dialog::around(TextView::new(BENCHMARK_TEXT).max_width(80))
                     .title("AOSC OS Installer")
                     .button("OK", move |s| {
-                        let config_clone_3 = config_clone_2.clone();
-                        let mirrors_clone_2 = mirrors_clone.clone();
-                        let loader = AsyncView::new_with_bg_creator(
-                            s,
-                            move || {
-                                let new_mirrors = network::speedtest_mirrors(mirrors_clone_2);
-                                Ok(new_mirrors)
-                            },
-                            move |mirrors| {
-                                let (config_view, repo_list) = select_mirror_view_base(&mirrors);
-                                select_mirrors_view(
-                                    config_view,
-                                    config_clone_3.clone(),
-                                    repo_list,
-                                    mirrors,
-                                )
-                            },
-                        );
                         s.pop_layer();
-                        s.add_layer(loader);
+                        benchmark_mirrors(s, mirrors_clone.clone(), config_clone_2.clone());
                     })
                     .button("Cancel", move |s| {
                         let mirrors_clone_3 = mirrors_clone_2.clone();
@@ -395,6 +860,7 @@ fn select_mirrors_view(
         })
         .button("Specify URL", move |s| {
             let config_clone = config_clone_3.clone();
+            let fallback_mirrors = fallback_mirrors_clone.clone();
             let url_input = Rc::new(RefCell::new(String::new()));
             let url_input_copy = url_input.clone();
             s.add_layer(
@@ -427,10 +893,10 @@ fn select_mirrors_view(
 
                     // Verify URL usability. At least we should be able to HEAD this mirror
                     let test_url = format!("{}{}", url_clone, config_clone.variant.as_ref().unwrap().url);
-                    let bench_result = network::query_file_meta(&test_url);
-                    if bench_result.is_err() {
+                    let bench_result = network::query_file_meta(&test_url, &config_clone.network);
+                    if let Err(e) = bench_result {
                         // this mirror is not usable, ask the user to check input.
-                        s.add_layer(Dialog::info(format!("Cannot access mirror! - Please make sure you have typed the URL correctly.\n\nError details:\n{}", bench_result.unwrap_err())));
+                        s.add_layer(Dialog::info(format!("Cannot access mirror! - Please make sure you have typed the URL correctly.\n\nError details:\n{}", e)));
                         return;
                     }
 
@@ -445,13 +911,18 @@ fn select_mirrors_view(
                         loc_tr: String::from("user-loc"),
                         url,
                     }));
+                    config_clone.fallback_mirrors = Some(fallback_mirrors.clone());
+                    config_clone.parallel_mirrors = parallel_mirrors(s);
+                    config_clone.stream_extract = stream_extract(s);
 
-                    if config_clone.partition.is_some() {
-                        s.pop_layer();
-                        s.add_layer(select_user_password(config_clone.clone()));
-                    } else {
-                        select_disk(s, config_clone);
-                    }
+                    rank_parallel_mirrors(s, config_clone, ranked, |s, config_clone| {
+                        if config_clone.partition.is_some() {
+                            s.pop_layer();
+                            s.add_layer(select_user_password(config_clone));
+                        } else {
+                            select_disk(s, config_clone);
+                        }
+                    });
                 })
                 .button("Cancel", |s| {
                     s.pop_layer();
@@ -466,6 +937,64 @@ fn select_mirrors_view(
         .button("Exit", |s| s.quit())
 }
 
+/// Parallel downloads use the first of `config.fallback_mirrors`, so rank them with a
+/// benchmark first (like `--parallel-mirrors`) unless the user has already run one
+fn rank_parallel_mirrors<F>(siv: &mut Cursive, mut config: InstallConfig, ranked: bool, next: F)
+where
+    F: FnOnce(&mut Cursive, InstallConfig) + Send + 'static,
+{
+    if ranked || config.parallel_mirrors.is_none() {
+        next(siv, config);
+        return;
+    }
+
+    siv.add_layer(wrap_in_dialog(
+        TextView::new("Benchmarking mirrors to download from the fastest ones ..."),
+        "Benchmarking Mirrors",
+        None,
+    ));
+    let cb_sink = siv.cb_sink().clone();
+    thread::spawn(move || {
+        let mirrors = config
+            .fallback_mirrors
+            .as_deref()
+            .cloned()
+            .unwrap_or_default();
+        let ranked = network::speedtest_mirrors(mirrors, &config.network);
+        cb_sink
+            .send(Box::new(move |s| {
+                s.pop_layer();
+                // keep the shown order if no mirror could be reached
+                if !ranked.is_empty() {
+                    config.fallback_mirrors = Some(Arc::new(ranked));
+                }
+                next(s, config);
+            }))
+            .ok();
+    });
+}
+
+fn parallel_mirrors(siv: &mut Cursive) -> Option<usize> {
+    siv.call_on_name("parallel_mirrors", |v: &mut Checkbox| v.is_checked())
+        .unwrap_or(false)
+        .then_some(DEFAULT_PARALLEL_MIRRORS)
+}
+
+fn stream_extract(siv: &mut Cursive) -> bool {
+    siv.call_on_name("stream_extract", |v: &mut Checkbox| v.is_checked())
+        .unwrap_or(false)
+}
+
+fn encrypt_partition(siv: &mut Cursive) -> bool {
+    siv.call_on_name("luks", |v: &mut Checkbox| v.is_checked())
+        .unwrap_or(false)
+}
+
+fn use_lvm(siv: &mut Cursive) -> bool {
+    siv.call_on_name("lvm", |v: &mut Checkbox| v.is_checked())
+        .unwrap_or(false)
+}
+
 fn select_partition(siv: &mut Cursive, config: InstallConfig, dev: Rc<DkDerive>) {
     let dev_clone = dev.clone();
     let path = dev.path.clone();
@@ -474,7 +1003,17 @@ fn select_partition(siv: &mut Cursive, config: InstallConfig, dev: Rc<DkDerive>)
 
     let view = AsyncView::new_with_bg_creator(
         siv,
-        move || Ok(disks::list_partitions(Some(path))),
+        move || {
+            let mut partitions = disks::list_partitions(Some(path.clone()));
+            // logical volumes on this drive can hold the system as well
+            partitions.extend(
+                disks::list_logical_volumes()
+                    .into_iter()
+                    .filter(|x| x.parent_path.as_ref() == Some(&path)),
+            );
+
+            Ok(partitions)
+        },
         move |partitions| {
             let (disk_list, disk_view) = make_partition_list(partitions);
             let disk_list = SendWrapper::new(disk_list);
@@ -508,13 +1047,15 @@ fn select_partition(siv: &mut Cursive, config: InstallConfig, dev: Rc<DkDerive>)
     let config_copy_2 = config.clone();
     let config_clone_3 = config.clone();
     let config_clone_4 = config.clone();
+    let config_clone_5 = config.clone();
+    let dev_alongside = dev.clone();
 
     siv.add_layer(
         wrap_in_dialog(config_view, "AOSC OS Installation", None)
         .button("Continue", move |s| {
             let disk_list = s.user_data::<SendWrapper<RadioGroup<disks::Partition>>>();
             let variant = config_clone_3.variant.as_ref().unwrap();
-            let required_size = variant.install_size + variant.size;
+            let required_size = variant.required_size();
             if let Some(disk_list) = disk_list {
                 let disk_list = disk_list.clone().take();
                 let current_partition = if cfg!(debug_assertions) {
@@ -570,7 +1111,8 @@ fn select_partition(siv: &mut Cursive, config: InstallConfig, dev: Rc<DkDerive>)
                 if let Some(fs_type) = fs_type {
                     if fs_type != "ext4" && ALLOWED_FS_TYPE.contains(&fs_type.as_str()) {
                         let view = wrap_in_dialog(LinearLayout::vertical()
-                        .child(TextView::new(format!(SURE_FS_TYPE_INFO!(), &fs_type))), "AOSC OS Installer", None)
+                        .child(TextView::new(format!(SURE_FS_TYPE_INFO!(), &fs_type)))
+                        .child(TextView::new(if fs_type == "btrfs" { BTRFS_LAYOUT_INFO } else { "" })), "AOSC OS Installer", None)
                         .button("Use Ext4", move |s| {
                             let new_part = disks::fill_fs_type(current_partition.as_ref(), true);
                             let mut config_clone = config_copy_2.clone();
@@ -643,7 +1185,23 @@ fn select_partition(siv: &mut Cursive, config: InstallConfig, dev: Rc<DkDerive>)
                 format!("- A {} system root partition will be created.", human_size(dev_size))
             };
 
-            auto_partition_view(s, config_clone_4.clone(), &select_device, &desc, path)
+            auto_partition_view(s, config_clone_4.clone(), &select_device, &desc, path, false)
+        })
+        .button("Install Alongside", move |s| {
+            let select_device = format!(
+                "{} ({}, {})",
+                dev_alongside.path.display(),
+                dev_alongside.model,
+                human_size(dev_alongside.size)
+            );
+
+            let desc = if is_efi_booted() {
+                "- The existing EFI System Partition (ESP) will be used, a 512MiB ESP will be created if there is none.\n- A system root partition will be created in the largest unallocated space.".to_string()
+            } else {
+                "- A system root partition will be created in the largest unallocated space.".to_string()
+            };
+
+            auto_partition_view(s, config_clone_5.clone(), &select_device, &desc, dev_alongside.path.clone(), true)
         })
         .button("Back", move |s| {
             s.pop_layer();
@@ -772,7 +1330,7 @@ If you continue, the contents of your hard disk will be erased. Please make sure
                 let device_path = device_path_1.clone();
                 let config_clone = config.clone();
                 s.pop_layer();
-                auto_partition_view(s, config_clone, &select_device, &desc, device_path);
+                auto_partition_view(s, config_clone, &select_device, &desc, device_path, false);
             })
             .button(btn_label, move |s| {
                 select_partition(s, config_clone_3.clone(), device.clone());
@@ -795,31 +1353,149 @@ fn auto_partition_view(
     select_device: &str,
     desc: &str,
     device_path: PathBuf,
+    alongside: bool,
 ) {
-    let tips = format!("WARNING: This will DESTROY ALL DATA ON THE SPECIFIED DRIVE, are you sure that you would want to proceed?\n\nSelect device: {select_device}\n\n{desc}");
+    let tips = if alongside {
+        format!("Existing partitions on the specified drive will be kept, AOSC OS will be installed into its largest unallocated space. Would you like to proceed?\n\nSelect device: {select_device}\n\n{desc}")
+    } else {
+        format!("WARNING: This will DESTROY ALL DATA ON THE SPECIFIED DRIVE, are you sure that you would want to proceed?\n\nSelect device: {select_device}\n\n{desc}")
+    };
+    let confirm_label = if alongside {
+        "Yes, Install Alongside"
+    } else {
+        "Yes, Please Partition My Drive!"
+    };
+    let passphrase = Rc::new(RefCell::new(String::new()));
+    let passphrase_copy = Rc::clone(&passphrase);
+    let passphrase_confirm = Rc::new(RefCell::new(String::new()));
+    let passphrase_confirm_copy = Rc::clone(&passphrase_confirm);
+    let lvm_root_size = Rc::new(RefCell::new(String::new()));
+    let lvm_root_size_copy = Rc::clone(&lvm_root_size);
+    let mut view = LinearLayout::vertical().child(TextView::new(tips));
+    // Retro boots without an initramfs, which is needed to unlock and activate the system partition
+    if !config_clone.retro {
+        view.add_child(DummyView {});
+        view.add_child(
+            LinearLayout::horizontal()
+                .child(Checkbox::new().with_name("luks"))
+                .child(TextView::new(
+                    " Encrypt the system partition (LUKS2), the passphrase is asked at every boot",
+                )),
+        );
+        view.add_child(
+            ListView::new()
+                .child(
+                    "Passphrase",
+                    EditView::new()
+                        .secret()
+                        .on_edit_mut(move |_, c, _| {
+                            passphrase_copy.replace(c.to_owned());
+                        })
+                        .min_width(20),
+                )
+                .child(
+                    "Confirm Passphrase",
+                    EditView::new()
+                        .secret()
+                        .on_edit_mut(move |_, c, _| {
+                            passphrase_confirm_copy.replace(c.to_owned());
+                        })
+                        .min_width(20),
+                ),
+        );
+        view.add_child(DummyView {});
+        view.add_child(
+            LinearLayout::horizontal()
+                .child(Checkbox::new().with_name("lvm"))
+                .child(TextView::new(
+                    " Use LVM, with a swap volume instead of a swapfile",
+                )),
+        );
+        view.add_child(TextView::new(
+            "To keep /home on its own volume, enter the size of the root volume, leave it empty otherwise.",
+        ));
+        view.add_child(
+            ListView::new().child(
+                "Root volume size (GiB)",
+                EditView::new()
+                    .on_edit_mut(move |_, c, _| {
+                        lvm_root_size_copy.replace(c.to_owned());
+                    })
+                    .min_width(20),
+            ),
+        );
+    }
     s.add_layer(
-        wrap_in_dialog(TextView::new(tips), "AOSC OS Installer", None)
-            .button("Yes, Please Partition My Drive!", move |s| {
-                let config_clone = config_clone.clone();
-                let config = config_clone.clone();
-                let variant = config.variant.unwrap();
+        wrap_in_dialog(view, "AOSC OS Installer", None)
+            .button(confirm_label, move |s| {
+                let mut config_clone = config_clone.clone();
+                if encrypt_partition(s) {
+                    let passphrase = passphrase.as_ref().to_owned().into_inner();
+                    let passphrase_confirm = passphrase_confirm.as_ref().to_owned().into_inner();
+                    if passphrase.is_empty() || passphrase_confirm.is_empty() {
+                        fill_in_all_the_fields!(s);
+                    }
+
+                    if passphrase != passphrase_confirm {
+                        show_msg(s, "Passphrases do not match.");
+                        return;
+                    }
+
+                    config_clone.luks_passphrase = Some(Arc::new(passphrase));
+                } else {
+                    config_clone.luks_passphrase = None;
+                }
+                config_clone.luks_passphrase_needed = false;
+                let variant = config_clone.variant.clone().unwrap();
+                let required_size = variant.required_size();
+                config_clone.lvm = None;
+                if use_lvm(s) {
+                    let root_size = lvm_root_size.as_ref().to_owned().into_inner();
+                    let root_size = if root_size.trim().is_empty() {
+                        None
+                    } else if let Ok(size) = root_size.trim().parse::<f64>() {
+                        Some((size * 1024.0 * 1024.0 * 1024.0) as u64)
+                    } else {
+                        show_msg(s, "Root volume size is not valid.");
+                        return;
+                    };
+                    if root_size.is_some_and(|x| x < required_size) {
+                        show_msg(
+                            s,
+                            &format!(
+                                "The root volume is too small to install AOSC OS release!\n\nRequired space: {:.3}GiB",
+                                required_size as f32 / 1024.0 / 1024.0 / 1024.0
+                            ),
+                        );
+                        return;
+                    }
+
+                    config_clone.lvm = Some(disks::LvmLayout { root_size });
+                }
                 let device_path = device_path.clone();
-                let required_size = variant.install_size + variant.size;
                 let view = AsyncView::new_with_bg_creator(
                     s,
-                    move || match auto_create_partitions(&device_path) {
-                        Ok(part) => {
-                            if required_size > part.size {
-                                return Err(format!(
-                                    "The specified partition does not contain enough space to install AOSC OS release!\n\nAvailable space: {:.3}GiB\nRequired space: {:.3}GiB", 
-                                    part.size as f32 / 1024.0 / 1024.0 / 1024.0,
-                                    required_size as f32 / 1024.0 / 1024.0 / 1024.0
-                                ));
+                    move || {
+                        // the partition is created in the free space once the installation starts
+                        let res = if alongside {
+                            disks::free_space_partition(&device_path, required_size)
+                        } else {
+                            auto_create_partitions(&device_path)
+                        };
+                        match res {
+                            Ok(part) => {
+                                if required_size > part.size {
+                                    return Err(format!(
+                                        "The specified partition does not contain enough space to install AOSC OS release!\n\nAvailable space: {:.3}GiB\nRequired space: {:.3}GiB", 
+                                        part.size as f32 / 1024.0 / 1024.0 / 1024.0,
+                                        required_size as f32 / 1024.0 / 1024.0 / 1024.0
+                                    ));
+                                }
+
+                                Ok(part)
                             }
-
-                            Ok(part)
-                        },
-                        Err(e) => Err(e.to_string()),
+                            Err(e) => Err(e.to_string()),
+                        }
                     },
                     move |res| {
                         let mut config = config_clone.clone();
@@ -870,11 +1546,158 @@ fn continue_to_format_hdd(s: &mut Cursive, config_clone: InstallConfig, fs_type:
 
 fn partition_view_to_next(s: &mut Cursive, config_clone: InstallConfig) {
     s.pop_layer();
-    if config_clone.user.is_some() {
-        is_use_last_config(s, config_clone);
+    select_mount_points(s, config_clone);
+}
+
+fn select_mount_points(siv: &mut Cursive, config: InstallConfig) {
+    let mount_points = config
+        .mount_points
+        .iter()
+        .map(|x| {
+            let fs_type = x.partition.fs_type.as_deref().unwrap_or_default();
+            format!(
+                "{} at {} ({})",
+                x.partition
+                    .path
+                    .as_deref()
+                    .unwrap_or(Path::new("?"))
+                    .display(),
+                x.mount_path.display(),
+                if x.format {
+                    format!("formatted as {fs_type}")
+                } else {
+                    format!("keeps its {fs_type} filesystem")
+                }
+            )
+        })
+        .collect::<Vec<_>>();
+    let mount_points = if mount_points.is_empty() {
+        "No other partitions will be mounted.".to_string()
     } else {
-        s.add_layer(select_user_password(config_clone));
+        mount_points.join("\n")
+    };
+    let config_copy = config.clone();
+    let config_copy_2 = config.clone();
+
+    siv.add_layer(
+        wrap_in_dialog(
+            LinearLayout::vertical()
+                .child(TextView::new(MOUNT_POINTS_TEXT))
+                .child(DummyView {})
+                .child(Panel::new(TextView::new(mount_points)).title("Mount Points")),
+            "AOSC OS Installer",
+            None,
+        )
+        .button("Continue", move |s| {
+            s.pop_layer();
+            if config.user.is_some() {
+                is_use_last_config(s, config.clone());
+            } else {
+                s.add_layer(select_user_password(config.clone()));
+            }
+        })
+        .button("Add Mount Point", move |s| {
+            add_mount_point(s, config_copy.clone());
+        })
+        .button("Clear", move |s| {
+            let mut config = config_copy_2.clone();
+            config.mount_points.clear();
+            s.pop_layer();
+            select_mount_points(s, config);
+        })
+        .button("Back", |s| {
+            s.pop_layer();
+        }),
+    );
+}
+
+fn add_mount_point(siv: &mut Cursive, config: InstallConfig) {
+    let root_path = config.partition.as_ref().and_then(|x| x.path.clone());
+    let mut partitions = disks::list_partitions(None);
+    partitions.extend(disks::list_logical_volumes());
+    partitions.retain(|x| {
+        x.path != root_path
+            && config
+                .mount_points
+                .iter()
+                .all(|y| y.partition.path != x.path)
+    });
+    let (partition_list, partition_view) = make_partition_list(partitions);
+
+    let mount_path = Rc::new(RefCell::new(String::new()));
+    let mount_path_copy = Rc::clone(&mount_path);
+    let mut fs_type_view = SelectView::new()
+        .popup()
+        .item("Keep current filesystem", None);
+    for fs_type in ALLOWED_FS_TYPE {
+        fs_type_view.add_item(format!("Format as {fs_type}"), Some(fs_type.to_string()));
     }
+
+    siv.add_layer(
+        wrap_in_dialog(
+            LinearLayout::vertical()
+                .child(Panel::new(partition_view).title("Select Partition"))
+                .child(DummyView {})
+                .child(
+                    ListView::new()
+                        .child(
+                            "Mount point",
+                            EditView::new()
+                                .on_edit_mut(move |_, c, _| {
+                                    mount_path_copy.replace(c.to_owned());
+                                })
+                                .min_width(20),
+                        )
+                        .child("Filesystem", fs_type_view.with_name("mount_fs_type")),
+                ),
+            "AOSC OS Installer",
+            None,
+        )
+        .button("OK", move |s| {
+            let partition = partition_list.selection();
+            if partition.path.is_none() {
+                show_msg(s, "Please select a partition.");
+                return;
+            }
+            let mount_path = mount_path.as_ref().to_owned().into_inner();
+            if mount_path.is_empty() {
+                fill_in_all_the_fields!(s);
+            }
+            let fs_type = s
+                .call_on_name("mount_fs_type", |v: &mut SelectView<Option<String>>| {
+                    v.selection()
+                })
+                .flatten()
+                .and_then(|x| (*x).clone());
+            let mut partition = partition.as_ref().clone();
+            let format = fs_type.is_some();
+            if let Some(fs_type) = fs_type {
+                partition.fs_type = Some(fs_type);
+            }
+
+            let mut config = config.clone();
+            config.mount_points.push(disks::MountPoint {
+                partition,
+                mount_path: PathBuf::from(mount_path.trim()),
+                format,
+            });
+            let root = config.partition.as_deref().unwrap();
+            if let Err(e) =
+                disks::check_mount_points(root, config.lvm.as_ref(), &config.mount_points)
+            {
+                show_msg(s, &e.to_string());
+                return;
+            }
+
+            // the mount point list is outdated as well
+            s.pop_layer();
+            s.pop_layer();
+            select_mount_points(s, config);
+        })
+        .button("Cancel", |s| {
+            s.pop_layer();
+        }),
+    );
 }
 
 fn select_user_password(config: InstallConfig) -> Dialog {
@@ -932,7 +1755,7 @@ fn select_user_password(config: InstallConfig) -> Dialog {
                 .with_name("pwd2"),
         );
 
-    if cfg!(feature = "is_retro") {
+    if config.retro {
         user_password_view = user_password_view
             .child(
                 "Root Password",
@@ -983,7 +1806,7 @@ fn select_user_password(config: InstallConfig) -> Dialog {
             return;
         }
 
-        if password.is_empty() || password_confirm.is_empty() || name.is_empty() || (cfg!(feature = "is_retro") && (root_password.is_empty() || root_password_confirm.is_empty())) {
+        if password.is_empty() || password_confirm.is_empty() || name.is_empty() || (config.retro && (root_password.is_empty() || root_password_confirm.is_empty())) {
             fill_in_all_the_fields!(s);
         }
 
@@ -1551,37 +2374,105 @@ fn is_use_last_config(siv: &mut Cursive, config: InstallConfig) {
             "AOSC OS Installer",
             None,
         )
-        .button("Yes", move |s| show_summary(s, config_copy.clone()))
+        .button("Yes", move |s| {
+            let config = config_copy.clone();
+            if config.luks_passphrase_needed && config.luks_passphrase.is_none() {
+                ask_luks_passphrase(s, config);
+            } else {
+                show_summary(s, config);
+            }
+        })
         .button("No", move |s| {
             fs::remove_file(LAST_USER_CONFIG_FILE).ok();
             let new_config = InstallConfig {
                 partition: config.clone().partition,
                 ..Default::default()
             };
-            select_variant(s, new_config);
+            select_variant(s, with_session_settings(new_config, &config));
         })
         .button("Exit", |s| s.quit()),
     );
 }
 
+/// The passphrase of an encrypted installation is never saved, ask for it when reusing its config
+fn ask_luks_passphrase(siv: &mut Cursive, config: InstallConfig) {
+    let passphrase = Rc::new(RefCell::new(String::new()));
+    let passphrase_copy = Rc::clone(&passphrase);
+    let passphrase_confirm = Rc::new(RefCell::new(String::new()));
+    let passphrase_confirm_copy = Rc::clone(&passphrase_confirm);
+    let config_copy = config.clone();
+    let view = LinearLayout::vertical()
+        .child(TextView::new(
+            "Your previous installation encrypted the system partition (LUKS2). Please enter the passphrase again, it is asked at every boot.",
+        ))
+        .child(DummyView {})
+        .child(
+            ListView::new()
+                .child(
+                    "Passphrase",
+                    EditView::new()
+                        .secret()
+                        .on_edit_mut(move |_, c, _| {
+                            passphrase_copy.replace(c.to_owned());
+                        })
+                        .min_width(20),
+                )
+                .child(
+                    "Confirm Passphrase",
+                    EditView::new()
+                        .secret()
+                        .on_edit_mut(move |_, c, _| {
+                            passphrase_confirm_copy.replace(c.to_owned());
+                        })
+                        .min_width(20),
+                ),
+        );
+    siv.add_layer(
+        wrap_in_dialog(view, "AOSC OS Installer", None)
+            .button("Continue", move |s| {
+                let passphrase = passphrase.as_ref().to_owned().into_inner();
+                let passphrase_confirm = passphrase_confirm.as_ref().to_owned().into_inner();
+                if passphrase.is_empty() || passphrase_confirm.is_empty() {
+                    fill_in_all_the_fields!(s);
+                }
+                if passphrase != passphrase_confirm {
+                    show_msg(s, "Passphrases do not match.");
+                    return;
+                }
+                let mut config = config.clone();
+                config.luks_passphrase = Some(Arc::new(passphrase));
+                config.luks_passphrase_needed = false;
+                s.pop_layer();
+                show_summary(s, config);
+            })
+            .button("Do Not Encrypt", move |s| {
+                let mut config = config_copy.clone();
+                config.luks_passphrase_needed = false;
+                s.pop_layer();
+                show_summary(s, config);
+            }),
+    );
+}
+
 fn show_summary(siv: &mut Cursive, config: InstallConfig) {
     let mut path = String::new();
     let mut fs = String::new();
     let config_copy = config.clone();
     let config_copy_2 = config.clone();
     if let Some(partition) = config.partition {
-        if let Some(partition) = &partition.path {
-            path = partition.to_string_lossy().to_string();
+        match (&partition.path, &partition.parent_path) {
+            (Some(p), _) => path = p.to_string_lossy().to_string(),
+            // created once the installation starts
+            (None, Some(dev)) => {
+                path = format!("new partition in the free space of {}", dev.display())
+            }
+            (None, None) => (),
         }
         if let Some(fs_type) = &partition.fs_type {
             fs = fs_type.clone();
         }
     }
-    let swap_size = if let Some(swap_size) = *config.swap_size {
-        swap_size
-    } else {
-        0.0
-    };
+    let swap_size = (*config.swap_size).unwrap_or(0.0);
     let swap_str;
     let mem = sysinfo::System::new_all().total_memory();
     match disks::get_recommend_swap_size(mem) {
@@ -1599,21 +2490,74 @@ fn show_summary(siv: &mut Cursive, config: InstallConfig) {
             return;
         }
     };
+    let source = match &config.source {
+        InstallSource::Remote(_) => config.mirror.unwrap().name.clone(),
+        InstallSource::Local(root) => root.display().to_string(),
+    };
     let s = format!(
         SUMMARY_TEXT!(),
         path,
         fs,
         config.variant.unwrap().name,
-        config.mirror.unwrap().name,
+        source,
         config.user.unwrap(),
         config.locale.unwrap(),
         config.timezone.unwrap(),
         config.tc.unwrap(),
     );
+    let luks_s = if config.luks_passphrase.is_some() {
+        "- The system partition will be encrypted with LUKS2.\n"
+    } else {
+        ""
+    };
+    let lvm_s = match config.lvm.as_ref().map(|x| x.root_size) {
+        Some(Some(root_size)) => format!(
+            "- An LVM volume group ({}) will be created, with a {}GiB root volume and a home volume taking the rest.\n",
+            disks::LVM_VG_NAME,
+            (root_size as f64 / 1024.0 / 1024.0 / 1024.0).round()
+        ),
+        Some(None) => format!(
+            "- An LVM volume group ({}) will be created, with a root volume taking all of it.\n",
+            disks::LVM_VG_NAME
+        ),
+        None => String::new(),
+    };
+    let mount_points_s = config
+        .mount_points
+        .iter()
+        .map(|x| {
+            let path = x
+                .partition
+                .path
+                .as_deref()
+                .unwrap_or(Path::new("?"))
+                .display();
+            let fs_type = x.partition.fs_type.as_deref().unwrap_or_default();
+            if x.format {
+                format!(
+                    "- {} will be erased, formatted as {} and mounted at {}.\n",
+                    path,
+                    fs_type,
+                    x.mount_path.display()
+                )
+            } else {
+                format!(
+                    "- {} will be mounted at {}.\n",
+                    path,
+                    x.mount_path.display()
+                )
+            }
+        })
+        .collect::<String>();
     let swap_s = if swap_size != 0.0 {
         format!(
-            "- A {}GiB swapfile will be created and enabled ({}).",
+            "- A {}GiB {} will be created and enabled ({}).",
             (swap_size / 1024.0 / 1024.0 / 1024.0).round(),
+            if config.lvm.is_some() {
+                "swap volume"
+            } else {
+                "swapfile"
+            },
             swap_str
         )
     } else {
@@ -1621,7 +2565,7 @@ fn show_summary(siv: &mut Cursive, config: InstallConfig) {
     };
     siv.add_layer(
         wrap_in_dialog(
-            TextView::new(format!("{s}{swap_s}")),
+            TextView::new(format!("{s}{luks_s}{lvm_s}{mount_points_s}{swap_s}")),
             "Pre-Installation Confirmation",
             None,
         )
@@ -1746,9 +2690,26 @@ fn start_install(siv: &mut Cursive, config: InstallConfig) {
     });
 }
 
+/// Keep the settings of this session (recipe source, network and Retro) in `config`,
+/// instead of the ones of the installation it was saved from
+fn with_session_settings(config: InstallConfig, session: &InstallConfig) -> InstallConfig {
+    InstallConfig {
+        source: session.source.clone(),
+        network: session.network.clone(),
+        allow_unsigned_recipe: session.allow_unsigned_recipe,
+        retro: session.retro,
+        ..config
+    }
+}
+
 fn save_user_config_to_file(config: InstallConfig, path: &str) -> Result<()> {
     let mut config_copy = config;
     config_copy.partition = None;
+    config_copy.mount_points.clear();
+    // the layout belongs to the partition, which is picked again
+    config_copy.lvm = None;
+    // the passphrase itself is never saved
+    config_copy.luks_passphrase_needed = config_copy.luks_passphrase.is_some();
     let file_str = serde_json::to_string(&config_copy)?;
     fs::File::create(LAST_USER_CONFIG_FILE)?;
     fs::write(path, file_str)?;
@@ -1776,22 +2737,38 @@ fn show_finished(siv: &mut Cursive) {
     );
 }
 
-pub fn tui_main() {
+pub fn tui_main(
+    source: InstallSource,
+    network: NetworkConfig,
+    allow_unsigned_recipe: bool,
+    retro: bool,
+) {
     let mut siv = cursive::default();
 
     siv.add_global_callback('~', cursive::Cursive::toggle_debug_console);
 
+    let network = Rc::new(RefCell::new(network));
+    let network_copy = network.clone();
     siv.add_layer(
         Dialog::around(TextView::new(WELCOME_TEXT))
             .title("Welcome")
-            .button("Let's Go", |s| {
+            .button("Let's Go", move |s| {
+                let session = InstallConfig {
+                    source: source.clone(),
+                    network: network.borrow().clone(),
+                    allow_unsigned_recipe,
+                    retro,
+                    ..Default::default()
+                };
                 if let Ok(config) = read_user_config_on_file() {
-                    select_disk(s, config);
+                    select_disk(s, with_session_settings(config, &session));
                 } else {
-                    let config = InstallConfig::default();
-                    select_variant(s, config);
+                    select_variant(s, session);
                 }
             })
+            .button("Network Settings", move |s| {
+                select_network(s, network_copy.clone())
+            })
             .padding_lrtb(2, 2, 1, 1)
             .max_width(80),
     );
@@ -1820,3 +2797,30 @@ pub fn tui_main() {
         }
     }
 }
+
+#[test]
+fn test_with_session_settings() {
+    let session = InstallConfig {
+        source: InstallSource::Local(PathBuf::from("/run/media/aosc")),
+        network: NetworkConfig::new(Some("http://proxy:3128"))
+            .unwrap()
+            .with_rate_limit(Some(2.0))
+            .unwrap(),
+        allow_unsigned_recipe: true,
+        retro: true,
+        ..Default::default()
+    };
+    let saved = InstallConfig {
+        hostname: Some("aosc".to_string()),
+        retro: false,
+        ..Default::default()
+    };
+    let saved = serde_json::from_str(&serde_json::to_string(&saved).unwrap()).unwrap();
+
+    let config = with_session_settings(saved, &session);
+    assert_eq!(config.source, session.source);
+    assert_eq!(config.network, session.network);
+    assert!(config.allow_unsigned_recipe);
+    assert!(config.retro);
+    assert_eq!(config.hostname.as_deref(), Some("aosc"));
+}
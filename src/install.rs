@@ -17,12 +17,21 @@ use std::sync::Arc;
 use std::{fs::File, path::Path};
 use sysinfo::System;
 
-use crate::disks::{fstab_entries, is_efi_booted, Partition};
+use crate::disks::{
+    btrfs_mount_options, fstab_entries, is_efi_booted, luks_close, lvm_close, Partition,
+    BTRFS_SUBVOLUMES,
+};
 use crate::network;
 use crate::parser::{list_mounts, list_zoneinfo, parse_languagelist};
 
 const BIND_MOUNTS: &[&str] = &["/dev", "/proc", "/sys", "/run/udev"];
 const EFIVARS_PATH: &str = "/sys/firmware/efi/efivars";
+const GRUB_DEFAULT_PATH: &str = "/etc/default/grub";
+const DRACUT_LUKS_CONF_PATH: &str = "/etc/dracut.conf.d/90-luks.conf";
+const DRACUT_LUKS_CONF: &str =
+    "add_dracutmodules+=\" crypt \"\ninstall_items+=\" /etc/crypttab \"\n";
+const DRACUT_LVM_CONF_PATH: &str = "/etc/dracut.conf.d/90-lvm.conf";
+const DRACUT_LVM_CONF: &str = "add_dracutmodules+=\" lvm \"\n";
 const SYSTEM_ZONEINFO1970_PATH: &str = "/usr/share/zoneinfo/zone1970.tab";
 const BUNDLED_ZONEINFO_LIST: &[u8] = include_bytes!("../res/zone1970.tab");
 pub const LANGUAGE_LIST: &[u8] = include_bytes!("../res/languagelist");
@@ -58,12 +67,70 @@ fn no_need_to_run_info(s: &str, str_is_retro: bool) {
     }
 }
 
-#[derive(Debug)]
+// enough to see the `ustar` magic of a plain tar archive
+const MAGIC_LEN: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ExtractFileType {
     Tar,
+    TarXz,
+    TarZst,
+    TarGz,
     Squashfs,
 }
 
+impl ExtractFileType {
+    /// Guess the format from the file name
+    pub fn from_url(url: &str) -> Option<Self> {
+        [
+            (".squashfs", ExtractFileType::Squashfs),
+            (".tar.xz", ExtractFileType::TarXz),
+            (".txz", ExtractFileType::TarXz),
+            (".tar.zst", ExtractFileType::TarZst),
+            (".tzst", ExtractFileType::TarZst),
+            (".tar.gz", ExtractFileType::TarGz),
+            (".tgz", ExtractFileType::TarGz),
+            (".tar", ExtractFileType::Tar),
+        ]
+        .into_iter()
+        .find(|(suffix, _)| url.ends_with(suffix))
+        .map(|(_, file_type)| file_type)
+    }
+
+    /// Detect the format from the first bytes of the archive
+    pub fn from_magic(magic: &[u8]) -> Option<Self> {
+        if magic.starts_with(b"hsqs") {
+            Some(ExtractFileType::Squashfs)
+        } else if magic.starts_with(b"\xfd7zXZ\0") {
+            Some(ExtractFileType::TarXz)
+        } else if magic.starts_with(b"\x28\xb5\x2f\xfd") {
+            Some(ExtractFileType::TarZst)
+        } else if magic.starts_with(b"\x1f\x8b") {
+            Some(ExtractFileType::TarGz)
+        } else if magic.get(257..262) == Some(&b"ustar"[..]) {
+            Some(ExtractFileType::Tar)
+        } else {
+            None
+        }
+    }
+
+    /// Detect the format from the first bytes of the archive, falling back to the file name
+    /// for archives without a magic (e.g. pre-POSIX tar)
+    pub fn detect(magic: &[u8], url: &str) -> Result<Self> {
+        Self::from_magic(magic)
+            .or_else(|| Self::from_url(url))
+            .ok_or_else(|| anyhow!("Unsupported system release format: {}", url))
+    }
+}
+
+/// Read the first bytes of `reader`, for `ExtractFileType::detect`
+fn read_magic<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut magic = Vec::with_capacity(MAGIC_LEN);
+    reader.take(MAGIC_LEN as u64).read_to_end(&mut magic)?;
+
+    Ok(magic)
+}
+
 /// Get the list of available locales
 pub fn get_locale_list() -> Result<Vec<(&'static str, &'static str, &'static str)>> {
     let res = parse_languagelist(LANGUAGE_LIST).map_err(|e| anyhow!("{e}"))?;
@@ -137,18 +204,112 @@ pub fn get_zoneinfo_list() -> Result<Vec<String>> {
     Ok(zoneinfo_list)
 }
 
-/// Extract the given .tar.xz stream and preserve all the file attributes
-fn extract_tar_xz<P: AsRef<Path>, R: Read>(reader: R, path: P) -> Result<()> {
-    let decompress = xz2::read::XzDecoder::new(reader);
+/// Open the given tar stream compressed as `file_type`, preserving all the file attributes
+fn tar_archive<'a, R: Read + 'a>(
+    reader: R,
+    file_type: ExtractFileType,
+) -> Result<tar::Archive<Box<dyn Read + 'a>>> {
+    let decompress: Box<dyn Read + 'a> = match file_type {
+        ExtractFileType::Tar => Box::new(reader),
+        ExtractFileType::TarXz => Box::new(xz2::read::XzDecoder::new(reader)),
+        ExtractFileType::TarZst => Box::new(zstd::stream::read::Decoder::new(reader)?),
+        // tarballs compressed by pigz consist of several gzip members
+        ExtractFileType::TarGz => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+        ExtractFileType::Squashfs => bail!("A squashfs image is not a tar archive."),
+    };
     let mut tar_processor = tar::Archive::new(decompress);
     tar_processor.set_unpack_xattrs(true);
     tar_processor.set_preserve_permissions(true);
     tar_processor.set_preserve_ownerships(true);
-    tar_processor.unpack(path)?;
+
+    Ok(tar_processor)
+}
+
+/// Extract the given tar stream and preserve all the file attributes
+fn extract_tar<P: AsRef<Path>, R: Read>(
+    reader: R,
+    file_type: ExtractFileType,
+    path: P,
+) -> Result<()> {
+    tar_archive(reader, file_type)?.unpack(path)?;
 
     Ok(())
 }
 
+/// Extract the given tar stream like `extract_tar`, detecting its compression from the data.
+/// Every path created while unpacking is recorded in `unpacked`, so that `remove_unpacked`
+/// can undo it if the stream turns out to be corrupted
+pub fn extract_tar_stream<R: Read>(
+    mut reader: R,
+    url: &str,
+    path: &Path,
+    unpacked: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let magic = read_magic(&mut reader)?;
+    let file_type = ExtractFileType::detect(&magic, url)?;
+    info!("Unpacking {} as {:?}", url, file_type);
+    let mut tar_processor = tar_archive(std::io::Cursor::new(magic).chain(reader), file_type)?;
+
+    let mut directories = vec![];
+    for entry in tar_processor.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.to_path_buf();
+        // `unpack_in` refuses these, so they never end up outside of `path`
+        if entry_path
+            .components()
+            .any(|x| x == std::path::Component::ParentDir)
+        {
+            continue;
+        }
+        // only what did not exist before is ours to remove again, including the parent
+        // directories `unpack_in` creates on the way
+        for ancestor in entry_path.ancestors() {
+            let target = path.join(ancestor);
+            if ancestor.as_os_str().is_empty() || target.symlink_metadata().is_ok() {
+                break;
+            }
+            unpacked.push(target);
+        }
+        // like `Archive::unpack`, directories are done last so that their permissions
+        // and modification times are not disturbed by the files inside
+        if entry.header().entry_type().is_dir() {
+            directories.push(entry);
+        } else {
+            entry.unpack_in(path)?;
+        }
+    }
+
+    directories.sort_by(|a, b| b.path_bytes().cmp(&a.path_bytes()));
+    for mut dir in directories {
+        dir.unpack_in(path)?;
+    }
+
+    Ok(())
+}
+
+/// Remove the paths recorded by `extract_tar_stream`, keeping everything inside the
+/// `kept` mount points (partitions whose data is kept, e.g. /home) and directories
+/// that are not empty after removing our files
+pub fn remove_unpacked(mut unpacked: Vec<PathBuf>, kept: &[PathBuf]) {
+    // children come before their parents
+    unpacked.sort_unstable_by(|a, b| b.cmp(a));
+    unpacked.dedup();
+
+    for path in unpacked {
+        if kept.iter().any(|x| path.starts_with(x)) {
+            continue;
+        }
+        let res = match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => std::fs::remove_dir(&path),
+            Ok(_) => std::fs::remove_file(&path),
+            Err(_) => continue,
+        };
+        if let Err(e) = res {
+            info!("Keeping {}: {}", path.display(), e);
+        }
+    }
+}
+
 /// Extract the .squashfs and callback download progress
 fn extract_squashfs<P: AsRef<Path>>(
     file_size: f64,
@@ -175,7 +336,7 @@ fn extract_squashfs<P: AsRef<Path>>(
     Ok(())
 }
 
-/// Extract .tar.xz or .squashfs
+/// Extract a tarball (plain, xz, zstd or gzip compressed) or a .squashfs image
 pub fn extract_file(
     file_size: f64,
     url: String,
@@ -183,22 +344,20 @@ pub fn extract_file(
     extract_path: &Path,
     counter: cursive::utils::Counter,
 ) -> Result<()> {
-    let extract_file_type = if url.ends_with(".squashfs") {
-        ExtractFileType::Squashfs
-    } else if url.ends_with(".tar.xz") {
-        ExtractFileType::Tar
-    } else {
-        return Err(anyhow!("Unsupport format!"));
-    };
+    let mut archive = std::fs::File::open(archive_path)?;
+    let extract_file_type = ExtractFileType::detect(&read_magic(&mut archive)?, &url)?;
+    archive.rewind()?;
+    info!("Unpacking {} as {:?}", url, extract_file_type);
 
     match extract_file_type {
-        ExtractFileType::Tar => extract_tar_xz(
-            ProgressReader::new(counter, std::fs::File::open(archive_path)?),
-            extract_path,
-        ),
         ExtractFileType::Squashfs => {
             extract_squashfs(file_size, archive_path, extract_path, counter)
         }
+        file_type => extract_tar(
+            ProgressReader::new(counter, archive),
+            file_type,
+            extract_path,
+        ),
     }
 }
 
@@ -216,8 +375,33 @@ pub fn sync_and_reboot() -> Result<()> {
     Ok(())
 }
 
-/// Mount the filesystem
+/// Mount the system partition, in the subvolume layout if it is Btrfs
 pub fn mount_root_path(partition: &Partition, target: &Path) -> Result<()> {
+    if partition.fs_type.as_deref() != Some("btrfs") {
+        return mount_partition(partition, target);
+    }
+    let source = partition
+        .path
+        .as_ref()
+        .ok_or_else(|| anyhow!("Installer failed to determine user-specified partition."))?;
+    // the system lives in subvolumes, mounted in order from `/`
+    for (subvolume, mount_point) in BTRFS_SUBVOLUMES {
+        let subvolume_target = target.join(mount_point.trim_start_matches('/'));
+        std::fs::create_dir_all(&subvolume_target)?;
+        mount::mount(
+            source,
+            &subvolume_target,
+            "btrfs",
+            mount::MountFlags::empty(),
+            btrfs_mount_options(subvolume).as_str(),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Mount the partition at `target` as is, without the subvolume layout of Btrfs root partitions
+pub fn mount_partition(partition: &Partition, target: &Path) -> Result<()> {
     if partition.fs_type.is_none() || partition.path.is_none() {
         return Err(anyhow!(
             "Installer failed to determine user-specified partition."
@@ -248,7 +432,20 @@ pub fn genfstab_to_file(partition: &Partition, root_path: &Path, mount_path: &Pa
     let fs_type = partition.fs_type.as_ref().ok_or_else(|| {
         anyhow!("Installer failed to detect filesystem type for the specified partition.")
     })?;
-    let s = fstab_entries(partition.path.as_ref(), fs_type, Some(mount_path))?;
+    let s = if fs_type == "btrfs" && mount_path == Path::new("/") {
+        let mut s = std::ffi::OsString::new();
+        for (subvolume, mount_point) in BTRFS_SUBVOLUMES {
+            s.push(fstab_entries(
+                partition.path.as_ref(),
+                fs_type,
+                Some(Path::new(mount_point)),
+                Some(btrfs_mount_options(subvolume).as_str()),
+            )?);
+        }
+        s
+    } else {
+        fstab_entries(partition.path.as_ref(), fs_type, Some(mount_path), None)?
+    };
     let mut f = std::fs::OpenOptions::new()
         .append(true)
         .open(root_path.join("etc/fstab"))?;
@@ -327,39 +524,101 @@ pub fn dive_into_guest(root: &Path) -> Result<()> {
     Ok(())
 }
 
-/// Runs dracut
+/// Runs dracut, Retro systems do not use an initramfs
 /// Must be used in a chroot context
-#[cfg(not(feature = "is_retro"))]
-pub fn execute_dracut() -> Result<()> {
+pub fn execute_dracut(retro: bool) -> Result<()> {
+    if retro {
+        no_need_to_run_info("dracut", true);
+        return Ok(());
+    }
+
     let cmd = "/usr/bin/update-initramfs";
     run_command(cmd, &[] as &[&str])?;
 
     Ok(())
 }
 
-/// Runs dracut (dummy function for retro mode)
-/// Must be used in a chroot context
-#[cfg(feature = "is_retro")]
-pub fn execute_dracut() -> Result<()> {
-    no_need_to_run_info("dracut", true);
+/// Sets up unlocking the LUKS container with the given UUID at boot:
+/// crypttab, the dracut crypt module and the GRUB configuration
+/// Must be used in a chroot context, before running dracut and grub-mkconfig
+pub fn setup_luks(uuid: &str) -> Result<()> {
+    let mut crypttab = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open("/etc/crypttab")?;
+    crypttab.write_all(format!("luks-{uuid} UUID={uuid} none luks\n").as_bytes())?;
+
+    write_dracut_conf(DRACUT_LUKS_CONF_PATH, DRACUT_LUKS_CONF)?;
+    // GRUB unlocks the container as well, to read /boot
+    update_grub_default(
+        &format!("rd.luks.uuid={uuid}"),
+        &[("GRUB_ENABLE_CRYPTODISK", "y")],
+    )
+}
+
+/// Sets up activating the root logical volume (`vg/lv`) at boot:
+/// the dracut lvm module and the GRUB configuration
+/// Must be used in a chroot context, before running dracut and grub-mkconfig
+pub fn setup_lvm(root_volume: &str) -> Result<()> {
+    write_dracut_conf(DRACUT_LVM_CONF_PATH, DRACUT_LVM_CONF)?;
+    update_grub_default(&format!("rd.lvm.lv={root_volume}"), &[])
+}
+
+fn write_dracut_conf(path: &str, conf: &str) -> Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, conf)?;
 
     Ok(())
 }
 
-/// Runs ssh-keygen -A (dummy function for non-retro mode)
-/// Must be used in a chroot context
-#[cfg(not(feature = "is_retro"))]
-pub fn gen_ssh_key() -> Result<()> {
-    no_need_to_run_info("ssh-keygen", false);
+fn update_grub_default(args: &str, vars: &[(&str, &str)]) -> Result<()> {
+    let config = std::fs::read_to_string(GRUB_DEFAULT_PATH).unwrap_or_default();
+    std::fs::write(GRUB_DEFAULT_PATH, grub_default_config(&config, args, vars))?;
 
     Ok(())
 }
 
-/// Runs ssh-keygen -A
+/// Append `args` to the kernel command line in /etc/default/grub, and set the variables in `vars`
+fn grub_default_config(config: &str, args: &str, vars: &[(&str, &str)]) -> String {
+    let mut has_cmdline = false;
+    let mut lines = config
+        .lines()
+        .filter(|line| {
+            !vars
+                .iter()
+                .any(|(name, _)| line.starts_with(&format!("{name}=")))
+        })
+        .map(|line| match line.strip_prefix("GRUB_CMDLINE_LINUX=") {
+            Some(cmdline) => {
+                has_cmdline = true;
+                let cmdline = cmdline.trim_matches(|c| c == '"' || c == '\'');
+                format!(
+                    "GRUB_CMDLINE_LINUX=\"{}\"",
+                    format!("{cmdline} {args}").trim()
+                )
+            }
+            None => line.to_string(),
+        })
+        .collect::<Vec<_>>();
+    if !has_cmdline {
+        lines.push(format!("GRUB_CMDLINE_LINUX=\"{args}\""));
+    }
+    lines.extend(vars.iter().map(|(name, value)| format!("{name}={value}")));
+
+    lines.join("\n") + "\n"
+}
+
+/// Runs ssh-keygen -A on Retro systems, mainline generates host keys on first boot
 /// Must be used in a chroot context
-#[cfg(feature = "is_retro")]
-pub fn gen_ssh_key() -> Result<()> {
-    run_command("ssh-keygen", &["-A"])?;
+pub fn gen_ssh_key(retro: bool) -> Result<()> {
+    if !retro {
+        no_need_to_run_info("ssh-keygen", false);
+        return Ok(());
+    }
+
+    run_command("ssh-keygen", ["-A"])?;
 
     Ok(())
 }
@@ -656,6 +915,8 @@ pub fn prepare_try_umount() -> Result<()> {
     for (_, mount_path) in dk_mounts {
         umount_root_path(Path::new(mount_path)).ok();
     }
+    lvm_close();
+    luks_close();
 
     Ok(())
 }
@@ -678,7 +939,9 @@ pub fn log_system_info() {
 }
 
 /// Create swapfile
-pub fn create_swapfile(size: f64, use_swap: bool, tempdir: &Path) -> Result<()> {
+/// With `nocow` (for Btrfs), the swapfile is neither copy-on-write nor compressed,
+/// as required by the kernel
+pub fn create_swapfile(size: f64, use_swap: bool, tempdir: &Path, nocow: bool) -> Result<()> {
     if !use_swap {
         return Ok(());
     }
@@ -687,6 +950,10 @@ pub fn create_swapfile(size: f64, use_swap: bool, tempdir: &Path) -> Result<()>
 
     info!("Creating swapfile");
     let mut swapfile = std::fs::File::create(&swap_path)?;
+    if nocow {
+        // only takes effect while the file is still empty, and also disables compression
+        run_command("chattr", [OsStr::new("+C"), swap_path.as_os_str()])?;
+    }
 
     let res = unsafe {
         libc::fallocate64(
@@ -717,9 +984,10 @@ pub fn swapoff(tempdir: &Path) {
     run_command("swapoff", [tempdir.join("swapfile")]).ok();
 }
 
+/// Adds the swapfile or swap volume at `swap` to fstab
 /// Must be used in a chroot context
-pub fn write_swap_entry_to_fstab() -> Result<()> {
-    let s = "/swapfile none swap defaults,nofail 0 0\n";
+pub fn write_swap_entry_to_fstab(swap: &Path) -> Result<()> {
+    let s = format!("{} none swap defaults,nofail 0 0\n", swap.display());
     let mut fstab = std::fs::OpenOptions::new()
         .append(true)
         .open("/etc/fstab")?;
@@ -739,6 +1007,8 @@ pub fn umount_all<F: AsFd>(mount_path: &Path, root_fd: F) {
     }
     swapoff(mount_path);
     umount_root_path(mount_path).ok();
+    lvm_close();
+    luks_close();
 }
 
 pub fn is_valid_hostname(hostname: &str) -> bool {
@@ -858,3 +1128,104 @@ _apt:x:976:976::/var/lib/apt:/sbin/nologin
     assert!(set_full_name("Mag Mell\n", "saki", passwd.clone()).is_err());
     assert!(set_full_name("Mag Mell:", "saki", passwd.clone()).is_err());
 }
+
+/// A system release containing only etc/hostname, compressed as `file_type`
+#[cfg(test)]
+fn test_archive(file_type: ExtractFileType) -> Vec<u8> {
+    let mut builder = tar::Builder::new(vec![]);
+    let mut header = tar::Header::new_gnu();
+    header.set_size(5);
+    header.set_mode(0o644);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_mtime(0);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, "etc/hostname", &b"aosc\n"[..])
+        .unwrap();
+    let tar = builder.into_inner().unwrap();
+
+    match file_type {
+        ExtractFileType::Tar => tar,
+        ExtractFileType::TarXz => {
+            let mut xz = xz2::write::XzEncoder::new(vec![], 1);
+            xz.write_all(&tar).unwrap();
+            xz.finish().unwrap()
+        }
+        ExtractFileType::TarZst => zstd::stream::encode_all(&tar[..], 0).unwrap(),
+        ExtractFileType::TarGz => {
+            let mut gz = flate2::write::GzEncoder::new(vec![], flate2::Compression::fast());
+            gz.write_all(&tar).unwrap();
+            gz.finish().unwrap()
+        }
+        ExtractFileType::Squashfs => unreachable!(),
+    }
+}
+
+#[test]
+fn test_remove_unpacked() {
+    let dir = tempfile::tempdir().unwrap();
+    let archive = test_archive(ExtractFileType::TarXz);
+
+    // a mount point that was there before unpacking
+    std::fs::create_dir_all(dir.path().join("efi/EFI")).unwrap();
+    let mut unpacked = vec![];
+    extract_tar_stream(&archive[..], "base.tar.xz", dir.path(), &mut unpacked).unwrap();
+    assert!(dir.path().join("etc/hostname").is_file());
+    assert_eq!(
+        unpacked,
+        [dir.path().join("etc/hostname"), dir.path().join("etc")]
+    );
+    remove_unpacked(unpacked, &[]);
+    assert!(!dir.path().join("etc").exists());
+    assert!(dir.path().join("efi/EFI").is_dir());
+
+    // files that were there before, or on a kept partition, stay
+    std::fs::create_dir_all(dir.path().join("etc")).unwrap();
+    std::fs::write(dir.path().join("etc/hostname"), "old\n").unwrap();
+    let mut unpacked = vec![];
+    extract_tar_stream(&archive[..], "base.tar.xz", dir.path(), &mut unpacked).unwrap();
+    assert!(unpacked.is_empty());
+    remove_unpacked(vec![dir.path().join("efi/EFI")], &[dir.path().join("efi")]);
+    assert!(dir.path().join("etc/hostname").is_file());
+    assert!(dir.path().join("efi/EFI").is_dir());
+}
+
+#[test]
+fn test_extract_file_type() {
+    use ExtractFileType::*;
+
+    for file_type in [Tar, TarXz, TarZst, TarGz] {
+        let data = test_archive(file_type);
+        // the magic wins over a misleading file name
+        assert_eq!(
+            ExtractFileType::detect(&data, "rootfs.tar.bz2").unwrap(),
+            file_type
+        );
+        let dir = tempfile::tempdir().unwrap();
+        extract_tar_stream(&data[..], "rootfs", dir.path(), &mut vec![]).unwrap();
+        assert!(dir.path().join("etc/hostname").is_file());
+    }
+    assert_eq!(
+        ExtractFileType::detect(b"", "base.squashfs").unwrap(),
+        Squashfs
+    );
+    assert!(ExtractFileType::detect(b"", "base.zip").is_err());
+}
+
+#[test]
+fn test_grub_default_config() {
+    let config = "GRUB_TIMEOUT=5\nGRUB_CMDLINE_LINUX=\"quiet splash\"\nGRUB_ENABLE_CRYPTODISK=n\n";
+    assert_eq!(
+        grub_default_config(config, "rd.luks.uuid=1234", &[("GRUB_ENABLE_CRYPTODISK", "y")]),
+        "GRUB_TIMEOUT=5\nGRUB_CMDLINE_LINUX=\"quiet splash rd.luks.uuid=1234\"\nGRUB_ENABLE_CRYPTODISK=y\n"
+    );
+    assert_eq!(
+        grub_default_config("GRUB_CMDLINE_LINUX=''\n", "rd.lvm.lv=aosc/root", &[]),
+        "GRUB_CMDLINE_LINUX=\"rd.lvm.lv=aosc/root\"\n"
+    );
+    assert_eq!(
+        grub_default_config("", "rd.luks.uuid=1234", &[("GRUB_ENABLE_CRYPTODISK", "y")]),
+        "GRUB_CMDLINE_LINUX=\"rd.luks.uuid=1234\"\nGRUB_ENABLE_CRYPTODISK=y\n"
+    );
+}
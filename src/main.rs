@@ -11,7 +11,10 @@ use sysinfo::{Pid, System};
 use clap::Parser;
 use frontend::Args;
 
+mod cache;
+mod checksum;
 mod disks;
+mod download;
 mod frontend;
 mod install;
 mod log;
@@ -41,7 +44,12 @@ fn execute() -> Result<()> {
     let args = std::env::args();
     if args.len() < 2 {
         LOG_FILE.get_or_try_init(|| setup_logger(false))?;
-        frontend::tui_main();
+        frontend::tui_main(
+            network::InstallSource::default(),
+            network::NetworkConfig::default(),
+            false,
+            network::detect_retro(),
+        );
     } else {
         let args = Args::parse();
         LOG_FILE.get_or_try_init(|| setup_logger(true))?;
@@ -1,14 +1,44 @@
 use anyhow::{anyhow, Result};
-use reqwest::{self, Client, Url};
+use futures::StreamExt;
+use log::{info, warn};
+use minisign_verify::{PublicKey, Signature};
+use reqwest::{self, Certificate, Client, Proxy, Url};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::{
     io::Write,
+    path::{Path, PathBuf},
     time::{Duration, Instant},
 };
+use time::OffsetDateTime;
 
 const MANIFEST_URL: &str = "https://releases.aosc.io/manifest/recipe.json";
-const IS_RETRO: bool = cfg!(feature = "is_retro");
+const RECIPE_VERSION: usize = 1;
+const LOCAL_RECIPE_FILE: &str = "recipe.json";
+const RECIPE_SIGNATURE_SUFFIX: &str = ".minisig";
+const RECIPE_PUBLIC_KEYS: &str = include_str!("../res/recipe-keys.pub");
+// AOSC OS/Retro is the only flavour available on these architectures
+const RETRO_ONLY_ARCHES: &[&str] = &[
+    "i486",
+    "powerpc",
+    "ppc64",
+    "armv6hf",
+    "armv7hf",
+    "loongson2f",
+];
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+// a mirror that sends nothing for this long is considered stalled
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+const SPEEDTEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_CONCURRENT_BENCHMARKS: usize = 4;
+// a rough upper bound of how much a system release grows once unpacked
+const ROOTFS_EXPANSION_RATIO: u64 = 4;
+// the first one found is used
+const SYSTEM_CA_BUNDLES: &[&str] = &[
+    "/etc/ssl/ca-bundle.crt",
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+];
 const SPEEDTEST_FILE_CHECKSUM: &str =
     "30e14955ebf1352266dc2ff8067e68104607e750abb9d3b36582b8af909fcb58";
 
@@ -31,31 +61,6 @@ pub struct Mirror {
     pub url: String,
 }
 
-#[derive(Deserialize, Debug, Clone)]
-pub struct Tarball {
-    pub arch: String,
-    pub date: String,
-    #[serde(rename = "downloadSize")]
-    pub download_size: i64,
-    #[serde(rename = "instSize")]
-    pub inst_size: i64,
-    pub path: String,
-    pub sha256sum: String,
-}
-
-#[derive(Deserialize, Debug, Clone)]
-pub struct Squashfs {
-    pub arch: String,
-    pub date: String,
-    #[serde(rename = "downloadSize")]
-    pub download_size: i64,
-    #[serde(rename = "instSize")]
-    pub inst_size: i64,
-    pub path: String,
-    pub sha256sum: String,
-    pub inodes: u32,
-}
-
 #[derive(Deserialize, Debug)]
 struct SystemRootFs {
     arch: String,
@@ -73,28 +78,50 @@ struct SystemRootFs {
 pub struct Variant {
     name: String,
     retro: bool,
-    pub description: String,
-    #[serde(rename = "description-tr")]
-    pub description_tr: String,
     tarballs: Vec<SystemRootFs>,
     squashfs: Vec<SystemRootFs>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct Bulletin {
     #[serde(rename = "type")]
     pub type_: String,
-    pub title: String,
+    title: String,
     #[serde(rename = "title-tr")]
-    pub title_tr: String,
-    pub body: String,
+    title_tr: String,
+    body: String,
     #[serde(rename = "body-tr")]
-    pub body_tr: String,
+    body_tr: String,
+}
+
+impl Bulletin {
+    /// Whether release engineers have left nothing for the user to read
+    pub fn is_empty(&self) -> bool {
+        self.type_ == "none" || (self.title().is_empty() && self.body().is_empty())
+    }
+
+    /// The installer has no translations of its own, so this is the untranslated title,
+    /// or the `title-tr` translation key if the recipe only has that
+    pub fn title(&self) -> &str {
+        localized(&self.title, &self.title_tr)
+    }
+
+    /// The body, like `title`
+    pub fn body(&self) -> &str {
+        localized(&self.body, &self.body_tr)
+    }
+}
+
+fn localized<'a>(text: &'a str, key: &'a str) -> &'a str {
+    if text.is_empty() {
+        key
+    } else {
+        text
+    }
 }
 
 #[derive(Deserialize, Debug)]
 pub struct Recipe {
-    pub version: usize,
     pub bulletin: Bulletin,
     variants: Vec<Variant>,
     mirrors: Vec<Mirror>,
@@ -110,21 +137,339 @@ pub struct VariantEntry {
     pub url: String,
 }
 
-pub fn fetch_recipe() -> Result<Recipe> {
-    Ok(reqwest::blocking::get(MANIFEST_URL)?
-        .error_for_status()?
-        .json()?)
+impl VariantEntry {
+    /// Space needed on the system partition: the release is saved there, then unpacked
+    pub fn required_size(&self) -> u64 {
+        self.install_size + self.size
+    }
 }
 
-pub fn fetch_mirrors(recipe: &Recipe) -> Vec<Mirror> {
-    recipe.mirrors.clone()
+/// Settings shared by every HTTP client the installer creates
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct NetworkConfig {
+    /// Proxy for all requests (http://, https://, socks5:// or socks5h://).
+    /// If not set, the `http_proxy`, `https_proxy` and `all_proxy` environment variables are used
+    pub proxy: Option<String>,
+    /// Extra PEM CA bundles to trust, e.g. for private mirrors signed by a company CA
+    #[serde(default)]
+    pub ca_certs: Vec<PathBuf>,
+    /// Also trust the CA bundle installed on the live system
+    #[serde(default)]
+    pub system_ca: bool,
+    /// Cap the download speed of system releases (bytes per second), e.g. on shared uplinks
+    #[serde(default)]
+    pub rate_limit: Option<u64>,
+}
+
+impl NetworkConfig {
+    /// Validate the proxy URL, an empty string means no explicit proxy
+    pub fn new(proxy: Option<&str>) -> Result<Self> {
+        let proxy = proxy.map(|x| x.trim()).filter(|x| !x.is_empty());
+        if let Some(proxy) = proxy {
+            Proxy::all(proxy).map_err(|e| anyhow!("Invalid proxy {proxy}: {e}"))?;
+        }
+
+        Ok(Self {
+            proxy: proxy.map(|x| x.to_string()),
+            ..Default::default()
+        })
+    }
+
+    /// Trust extra CA bundles, checking they can be loaded
+    pub fn with_ca_certs(mut self, ca_certs: Vec<PathBuf>, system_ca: bool) -> Result<Self> {
+        self.ca_certs = ca_certs;
+        self.system_ca = system_ca;
+        self.certificates()?;
+
+        Ok(self)
+    }
+
+    /// Limit the download speed to `rate_limit` MiB/s, `None` means no limit
+    pub fn with_rate_limit(mut self, rate_limit: Option<f64>) -> Result<Self> {
+        self.rate_limit = match rate_limit {
+            Some(x) if x.is_finite() && x > 0.0 => Some((x * 1024.0 * 1024.0) as u64),
+            Some(x) => return Err(anyhow!("Invalid download speed limit: {x}")),
+            None => None,
+        };
+
+        Ok(self)
+    }
+
+    fn certificates(&self) -> Result<Vec<Certificate>> {
+        let mut bundles = self.ca_certs.clone();
+        if self.system_ca {
+            let system_bundle = SYSTEM_CA_BUNDLES
+                .iter()
+                .map(PathBuf::from)
+                .find(|x| x.is_file())
+                .ok_or_else(|| {
+                    anyhow!("Installer could not find the CA bundle of the live system.")
+                })?;
+            bundles.push(system_bundle);
+        }
+
+        let mut certs = vec![];
+        for bundle in bundles {
+            let pem = std::fs::read(&bundle).map_err(|e| {
+                anyhow!(
+                    "Installer could not read CA bundle {}: {}",
+                    bundle.display(),
+                    e
+                )
+            })?;
+            let bundle_certs = Certificate::from_pem_bundle(&pem).map_err(|e| {
+                anyhow!(
+                    "Installer could not parse CA bundle {}: {}",
+                    bundle.display(),
+                    e
+                )
+            })?;
+            if bundle_certs.is_empty() {
+                return Err(anyhow!(
+                    "Installer could not find any CA certificate in {}.",
+                    bundle.display()
+                ));
+            }
+            certs.extend(bundle_certs);
+        }
+
+        Ok(certs)
+    }
+
+    fn proxy(&self) -> Result<Option<Proxy>> {
+        Ok(match &self.proxy {
+            Some(proxy) => Some(Proxy::all(proxy)?),
+            None => None,
+        })
+    }
+
+    pub fn client(&self) -> Result<Client> {
+        let mut builder = Client::builder()
+            .user_agent(DEPLOYKIT_USER_AGENT!())
+            .connect_timeout(CONNECT_TIMEOUT)
+            .read_timeout(READ_TIMEOUT);
+        if let Some(proxy) = self.proxy()? {
+            builder = builder.proxy(proxy);
+        }
+        for cert in self.certificates()? {
+            builder = builder.add_root_certificate(cert);
+        }
+
+        Ok(builder.build()?)
+    }
+
+    pub fn blocking_client(&self) -> Result<reqwest::blocking::Client> {
+        // the blocking client has no read timeout, but it only fetches small files,
+        // so bounding the whole request is enough
+        let mut builder = reqwest::blocking::Client::builder()
+            .user_agent(DEPLOYKIT_USER_AGENT!())
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(READ_TIMEOUT);
+        if let Some(proxy) = self.proxy()? {
+            builder = builder.proxy(proxy);
+        }
+        for cert in self.certificates()? {
+            builder = builder.add_root_certificate(cert);
+        }
+
+        Ok(builder.build()?)
+    }
+}
+
+/// Where to read the recipe and system releases from
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum InstallSource {
+    /// Fetch the recipe from the given URL and download system releases from mirrors
+    Remote(String),
+    /// Read `recipe.json` and system releases from a local directory,
+    /// laid out the same way as a mirror
+    Local(PathBuf),
+}
+
+impl Default for InstallSource {
+    fn default() -> Self {
+        InstallSource::Remote(MANIFEST_URL.to_string())
+    }
+}
+
+impl InstallSource {
+    /// Parse a recipe URL (http:// or https://), a `file://` URL or a plain path
+    pub fn from_location(location: &str) -> Result<Self> {
+        if location.starts_with("http://") || location.starts_with("https://") {
+            Url::parse(location)?;
+            return Ok(InstallSource::Remote(location.to_string()));
+        }
+
+        Self::from_local(location)
+    }
+
+    /// Parse a local source from a plain path or a `file://` URL
+    pub fn from_local(location: &str) -> Result<Self> {
+        let path = Path::new(location.strip_prefix("file://").unwrap_or(location));
+        if !path.join(LOCAL_RECIPE_FILE).is_file() {
+            return Err(anyhow!(
+                "Installer could not find {} in the specified local source: {}",
+                LOCAL_RECIPE_FILE,
+                path.display()
+            ));
+        }
+
+        Ok(InstallSource::Local(path.to_path_buf()))
+    }
+
+    /// Get the path of the given system release if it is stored locally
+    pub fn local_rootfs(&self, variant: &VariantEntry) -> Option<PathBuf> {
+        // releases from recipes are relative, custom ones given by path are absolute
+        let path = Path::new(&variant.url);
+        if path.is_absolute() {
+            return Some(path.to_path_buf());
+        }
+
+        match self {
+            InstallSource::Remote(_) => None,
+            InstallSource::Local(root) => Some(root.join(&variant.url)),
+        }
+    }
+
+    pub fn is_remote(&self) -> bool {
+        matches!(self, InstallSource::Remote(_))
+    }
+
+    /// The recipe URL or local directory this source reads from
+    pub fn location(&self) -> String {
+        match self {
+            InstallSource::Remote(url) => url.clone(),
+            InstallSource::Local(root) => root.display().to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RecipeVersion {
+    version: usize,
+}
+
+/// Check the recipe schema version before parsing the whole recipe
+fn parse_recipe(data: &[u8]) -> Result<Recipe> {
+    let RecipeVersion { version } = serde_json::from_slice(data)
+        .map_err(|e| anyhow!("Installer could not read the recipe version: {e}"))?;
+
+    match version {
+        RECIPE_VERSION => serde_json::from_slice(data)
+            .map_err(|e| anyhow!("Installer could not parse the recipe: {e}")),
+        v if v > RECIPE_VERSION => Err(anyhow!(
+            "The recipe (version {}) is newer than what this installer supports (version {}).\n\nPlease update the installer.",
+            v,
+            RECIPE_VERSION
+        )),
+        v => Err(anyhow!(
+            "The recipe (version {}) is no longer supported by this installer (version {}).",
+            v,
+            RECIPE_VERSION
+        )),
+    }
+}
+
+/// Parse the trusted recipe keys, one key per line, skipping comments
+fn parse_recipe_keys(keys: &str) -> Result<Vec<PublicKey>> {
+    keys.lines()
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty() && !x.starts_with('#'))
+        .map(|x| {
+            PublicKey::from_base64(x)
+                .map_err(|e| anyhow!("Installer could not parse a trusted recipe key: {e}"))
+        })
+        .collect()
+}
+
+/// Check the detached minisign `signature` of the recipe against the trusted `keys`
+fn verify_recipe(data: &[u8], signature: &str, keys: &[PublicKey]) -> Result<()> {
+    let signature = Signature::decode(signature)
+        .map_err(|e| anyhow!("Installer could not parse the recipe signature: {e}"))?;
+
+    if keys
+        .iter()
+        .any(|x| x.verify(data, &signature, false).is_ok())
+    {
+        return Ok(());
+    }
+
+    Err(anyhow!(
+        "The recipe signature could not be verified with any key trusted by this installer.\n\nThe recipe may have been tampered with."
+    ))
 }
 
-pub fn get_variants() -> Result<Vec<VariantEntry>> {
-    let recipe = fetch_recipe()?;
-    let variants = find_variant_candidates(recipe)?;
+/// Fetch the recipe and, unless `allow_unsigned` is set, verify its detached signature
+pub fn fetch_recipe(
+    source: &InstallSource,
+    network: &NetworkConfig,
+    allow_unsigned: bool,
+) -> Result<Recipe> {
+    let keys = parse_recipe_keys(RECIPE_PUBLIC_KEYS)?;
+    if keys.is_empty() && !allow_unsigned {
+        return Err(anyhow!(
+            "This installer has no trusted key to verify the recipe signature with.\n\nOnly continue without verification (allow unsigned recipes) if you trust the recipe source."
+        ));
+    }
+    let (data, signature) = match source {
+        InstallSource::Remote(url) => {
+            let client = network.blocking_client()?;
+            let data = client
+                .get(url)
+                .send()?
+                .error_for_status()?
+                .bytes()?
+                .to_vec();
+            let signature = if allow_unsigned {
+                None
+            } else {
+                let signature_url = format!("{url}{RECIPE_SIGNATURE_SUFFIX}");
+                Some(
+                    client
+                        .get(&signature_url)
+                        .send()
+                        .and_then(|x| x.error_for_status())
+                        .and_then(|x| x.text())
+                        .map_err(|e| {
+                            anyhow!("Installer could not fetch the recipe signature from {signature_url}: {e}")
+                        })?,
+                )
+            };
+
+            (data, signature)
+        }
+        InstallSource::Local(root) => {
+            let data = std::fs::read(root.join(LOCAL_RECIPE_FILE))?;
+            let signature = if allow_unsigned {
+                None
+            } else {
+                let signature_file =
+                    root.join(format!("{LOCAL_RECIPE_FILE}{RECIPE_SIGNATURE_SUFFIX}"));
+                Some(std::fs::read_to_string(&signature_file).map_err(|e| {
+                    anyhow!(
+                        "Installer could not read the recipe signature {}: {}",
+                        signature_file.display(),
+                        e
+                    )
+                })?)
+            };
 
-    Ok(variants)
+            (data, signature)
+        }
+    };
+
+    match signature {
+        Some(signature) => verify_recipe(&data, &signature, &keys)?,
+        None => {
+            warn!("Recipe signature verification is disabled, the recipe is not authenticated!")
+        }
+    }
+
+    parse_recipe(&data)
+}
+
+pub fn fetch_mirrors(recipe: &Recipe) -> Vec<Mirror> {
+    recipe.mirrors.clone()
 }
 
 /// AOSC OS specific architecture mapping for ppc64
@@ -156,6 +501,10 @@ pub(crate) fn get_arch_name() -> Option<&'static str> {
         "x86" => Some("i486"),
         "powerpc" => Some("powerpc"),
         "aarch64" => Some("arm64"),
+        "arm" if cfg!(target_feature = "v7") => Some("armv7hf"),
+        "arm" => Some("armv6hf"),
+        // both are mips64el, only the CPU tells them apart
+        "mips64" if is_loongson2f() => Some("loongson2f"),
         "mips64" => Some("loongson3"),
         "riscv64" => Some("riscv64"),
         "loongarch64" => Some("loongarch64"),
@@ -163,13 +512,34 @@ pub(crate) fn get_arch_name() -> Option<&'static str> {
     }
 }
 
+#[cfg(not(target_arch = "powerpc64"))]
+fn is_loongson2f() -> bool {
+    std::fs::read_to_string("/proc/cpuinfo")
+        .map(|x| {
+            x.lines()
+                .any(|x| x.starts_with("cpu model") && x.contains("Loongson-2"))
+        })
+        .unwrap_or(false)
+}
+
+/// Whether to install AOSC OS/Retro rather than mainline AOSC OS by default
+///
+/// Builds with the `is_retro` feature always default to Retro.
+pub fn detect_retro() -> bool {
+    cfg!(feature = "is_retro")
+        || get_arch_name()
+            .map(|x| RETRO_ONLY_ARCHES.contains(&x))
+            .unwrap_or(false)
+}
+
 /// Issue a HEAD request to the specified url instead of downloading the entire body.
 ///
 /// If the server returned a error code the response becomes an error.
-pub fn query_file_meta(url: &String) -> Result<reqwest::blocking::Response> {
-    let client = reqwest::blocking::ClientBuilder::new()
-        .user_agent(DEPLOYKIT_USER_AGENT!())
-        .build()?;
+pub fn query_file_meta(
+    url: &String,
+    network: &NetworkConfig,
+) -> Result<reqwest::blocking::Response> {
+    let client = network.blocking_client()?;
     let head_response = client.head(url).send();
 
     let server_response = head_response?;
@@ -178,50 +548,152 @@ pub fn query_file_meta(url: &String) -> Result<reqwest::blocking::Response> {
     Ok(server_success)
 }
 
-pub fn speedtest_mirrors(mirrors: Vec<Mirror>) -> Vec<Mirror> {
-    let mut speedtest_mirror = vec![];
+/// How well a mirror performed in `benchmark_mirrors`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MirrorScore {
+    /// Time until the response headers arrived
+    #[serde(rename = "latency-ms", serialize_with = "serialize_millis")]
+    pub latency: Duration,
+    /// Bytes per second while receiving the test file
+    pub throughput: f64,
+}
+
+impl std::fmt::Display for MirrorScore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let throughput = self.throughput / 1024.0;
+        if throughput > 1024.0 {
+            write!(f, "{:.1}MiB/s", throughput / 1024.0)?;
+        } else {
+            write!(f, "{throughput:.1}KiB/s")?;
+        }
+
+        write!(f, ", {}ms", self.latency.as_millis())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MirrorBenchmark {
+    #[serde(flatten)]
+    pub mirror: Mirror,
+    /// Serialized as `score` and `error`, one of which is null
+    #[serde(flatten, serialize_with = "serialize_score")]
+    pub score: Result<MirrorScore, String>,
+}
+
+fn serialize_millis<S: serde::Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_millis() as u64)
+}
+
+fn serialize_score<S: serde::Serializer>(
+    score: &Result<MirrorScore, String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    use serde::ser::SerializeMap;
+
+    let mut map = serializer.serialize_map(Some(2))?;
+    map.serialize_entry("score", &score.as_ref().ok())?;
+    map.serialize_entry("error", &score.as_ref().err())?;
+    map.end()
+}
+
+/// Rank mirrors for downloading: highest throughput first, then lowest latency,
+/// mirrors that failed the benchmark last
+fn rank_mirrors(results: &mut [MirrorBenchmark]) {
+    results.sort_by(|a, b| match (&a.score, &b.score) {
+        (Ok(a), Ok(b)) => b
+            .throughput
+            .total_cmp(&a.throughput)
+            .then(a.latency.cmp(&b.latency)),
+        (Ok(_), Err(_)) => std::cmp::Ordering::Less,
+        (Err(_), Ok(_)) => std::cmp::Ordering::Greater,
+        (Err(_), Err(_)) => std::cmp::Ordering::Equal,
+    });
+}
+
+/// Benchmark at most `MAX_CONCURRENT_BENCHMARKS` mirrors at a time, calling `on_result`
+/// as soon as each of them is done. Returns every mirror with its score, ranked
+pub fn benchmark_mirrors<F>(
+    mirrors: Vec<Mirror>,
+    network: &NetworkConfig,
+    on_result: F,
+) -> Result<Vec<MirrorBenchmark>>
+where
+    F: Fn(&MirrorBenchmark),
+{
     let runtime = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .worker_threads(2)
-        .build()
-        .unwrap();
-    let client = reqwest::Client::builder()
-        .user_agent(DEPLOYKIT_USER_AGENT!())
-        .timeout(Duration::from_secs(10))
-        .build()
-        .unwrap();
-
-    runtime.block_on(async move {
-        let mut task = vec![];
-        for mirror in &mirrors {
-            task.push(get_mirror_speed_score(&mirror.url, &client))
-        }
-        let results = futures::future::join_all(task).await;
-        for (index, result) in results.into_iter().enumerate() {
-            if let Ok(score) = result {
-                speedtest_mirror.push((mirrors[index].loc_tr.to_owned(), score));
+        .build()?;
+    let client = network.client()?;
+
+    let mut results = runtime.block_on(async {
+        let client = &client;
+        let mut tasks = futures::stream::iter(mirrors.into_iter().map(|mirror| async move {
+            let score = get_mirror_speed_score(&mirror.url, client)
+                .await
+                .map_err(|e| e.to_string());
+            MirrorBenchmark { mirror, score }
+        }))
+        .buffer_unordered(MAX_CONCURRENT_BENCHMARKS);
+
+        let mut results = vec![];
+        while let Some(result) = tasks.next().await {
+            match &result.score {
+                Ok(score) => info!("Mirror {}: {}", result.mirror.name, score),
+                Err(e) => warn!("Mirror {} failed benchmark: {}", result.mirror.name, e),
             }
+            on_result(&result);
+            results.push(result);
         }
-        speedtest_mirror.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
-        let mut new_mirrors = vec![];
-        for (name, _) in speedtest_mirror {
-            let index = mirrors.iter().position(|x| x.loc_tr == name).unwrap();
-            new_mirrors.push(mirrors[index].to_owned());
+
+        results
+    });
+    rank_mirrors(&mut results);
+
+    Ok(results)
+}
+
+/// Benchmark mirrors and return the usable ones, fastest first
+pub fn speedtest_mirrors(mirrors: Vec<Mirror>, network: &NetworkConfig) -> Vec<Mirror> {
+    match benchmark_mirrors(mirrors, network, |_| {}) {
+        Ok(results) => results
+            .into_iter()
+            .filter(|x| x.score.is_ok())
+            .map(|x| x.mirror)
+            .collect(),
+        Err(e) => {
+            warn!("Installer failed to benchmark mirrors: {}", e);
+            vec![]
         }
-        new_mirrors
-    })
+    }
 }
 
-async fn get_mirror_speed_score(mirror_url: &str, client: &Client) -> Result<f32> {
+async fn get_mirror_speed_score(mirror_url: &str, client: &Client) -> Result<MirrorScore> {
     let download_url = Url::parse(mirror_url)?.join("../.repotest")?;
     let timer = Instant::now();
-    let file = client.get(download_url).send().await?.bytes().await?;
+    let resp = client
+        .get(download_url)
+        .timeout(SPEEDTEST_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?;
+    let latency = timer.elapsed();
+
+    let timer = Instant::now();
+    let file = resp.bytes().await?;
+    let transfer_time = timer.elapsed().as_secs_f64();
     let mut hasher = Sha256::new();
     hasher.write_all(&file)?;
 
     if hex::encode(hasher.finalize()) == SPEEDTEST_FILE_CHECKSUM {
-        let result_time = timer.elapsed().as_secs_f32();
-        return Ok(result_time);
+        return Ok(MirrorScore {
+            latency,
+            // avoid dividing by zero on a very fast local mirror
+            throughput: file.len() as f64 / transfer_time.max(0.001),
+        });
     }
 
     Err(anyhow!(
@@ -230,7 +702,9 @@ async fn get_mirror_speed_score(mirror_url: &str, client: &Client) -> Result<f32
     ))
 }
 
-pub fn find_variant_candidates(recipes: Recipe) -> Result<Vec<VariantEntry>> {
+/// List every release of the variants available for this device,
+/// sorted by variant name and then from the newest to the oldest
+pub fn find_variant_candidates(recipes: Recipe, retro: bool) -> Result<Vec<VariantEntry>> {
     let mut results: Vec<VariantEntry> = Vec::new();
     let arch_name = get_arch_name();
     if arch_name.is_none() {
@@ -243,15 +717,15 @@ pub fn find_variant_candidates(recipes: Recipe) -> Result<Vec<VariantEntry>> {
         .variants
         .into_iter()
         .filter(|x| {
-            ((x.retro && IS_RETRO && !x.tarballs.is_empty())
-                || (!x.retro && !IS_RETRO && !x.squashfs.is_empty()))
+            ((x.retro && retro && !x.tarballs.is_empty())
+                || (!x.retro && !retro && !x.squashfs.is_empty()))
                 && x.name != "BuildKit"
         })
         .collect::<Vec<Variant>>();
 
     let right_recipes_len = right_recipes.len();
     for (index, recipe) in right_recipes.into_iter().enumerate() {
-        let rootfs = match IS_RETRO {
+        let rootfs = match retro {
             true => recipe.tarballs,
             false => recipe.squashfs,
         };
@@ -271,17 +745,364 @@ pub fn find_variant_candidates(recipes: Recipe) -> Result<Vec<VariantEntry>> {
         }
         all_empty = false;
 
-        let candidate_rootfs = sorted_rootfs.first().unwrap();
-        results.push(VariantEntry {
-            name: recipe.name,
-            size: candidate_rootfs.download_size as u64,
-            install_size: candidate_rootfs.inst_size as u64,
-            date: candidate_rootfs.date.clone(),
-            url: candidate_rootfs.path.clone(),
-            sha256sum: candidate_rootfs.sha256sum.clone(),
-        });
+        for rootfs in sorted_rootfs {
+            results.push(VariantEntry {
+                name: recipe.name.clone(),
+                size: rootfs.download_size as u64,
+                install_size: rootfs.inst_size as u64,
+                date: rootfs.date,
+                url: rootfs.path,
+                sha256sum: rootfs.sha256sum,
+            });
+        }
     }
+    // stable sort, releases of the same variant stay newest first
     results.sort_by(|a, b| a.name.cmp(&b.name));
 
     Ok(results)
 }
+
+/// Keep only the newest release of each variant from `find_variant_candidates`
+pub fn latest_variants(variants: &[VariantEntry]) -> Vec<VariantEntry> {
+    let mut results: Vec<VariantEntry> = Vec::new();
+    for variant in variants {
+        if results
+            .last()
+            .map(|x| x.name != variant.name)
+            .unwrap_or(true)
+        {
+            results.push(variant.clone());
+        }
+    }
+
+    results
+}
+
+/// Describe a system release that is not listed in any recipe (e.g. an unreleased build under test),
+/// so that it goes through the same download, verification and extraction as a regular one.
+///
+/// `location` is either a http(s):// URL or a local path. If `size` is not given, it is read from
+/// the file or queried from the server; `install_size` is estimated from `size` if not given.
+/// A remote release comes with the mirror it is downloaded from, a local one is read in place.
+pub fn custom_rootfs(
+    location: &str,
+    sha256sum: &str,
+    size: Option<u64>,
+    install_size: Option<u64>,
+    network: &NetworkConfig,
+) -> Result<(Option<Mirror>, VariantEntry)> {
+    let sha256sum = sha256sum.trim().to_lowercase();
+    if sha256sum.len() != 64 || !sha256sum.chars().all(|x| x.is_ascii_hexdigit()) {
+        return Err(anyhow!("{} is not a valid SHA-256 checksum", sha256sum));
+    }
+
+    let (mirror, file_name, url, size, date) =
+        if location.starts_with("http://") || location.starts_with("https://") {
+            let mut url = Url::parse(location)?;
+            url.set_fragment(None);
+            let file_name = url
+                .path_segments()
+                .and_then(|mut x| x.next_back())
+                .filter(|x| !x.is_empty())
+                .map(|x| x.to_string())
+                .ok_or_else(|| anyhow!("{} does not point to a file", location))?;
+            let size = match size {
+                Some(size) => size,
+                None => query_file_meta(&url.to_string(), network)?
+                    .content_length()
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "Installer could not get the size of {}, please specify it",
+                            location
+                        )
+                    })?,
+            };
+            // the release is downloaded as `mirror.url` + `variant.url`
+            let variant_url = match url.query() {
+                Some(query) => format!("{file_name}?{query}"),
+                None => file_name.clone(),
+            };
+            let mut base = url.clone();
+            base.set_query(None);
+            base.path_segments_mut()
+                .map_err(|_| anyhow!("{} does not point to a file", location))?
+                .pop()
+                .push("");
+            let host = url.host_str().unwrap_or_default().to_string();
+            let mirror = Mirror {
+                name: host.clone(),
+                name_tr: host.clone(),
+                loc: host.clone(),
+                loc_tr: host,
+                url: base.to_string(),
+            };
+
+            (
+                Some(mirror),
+                file_name,
+                variant_url,
+                size,
+                OffsetDateTime::now_utc(),
+            )
+        } else {
+            let path = Path::new(location.strip_prefix("file://").unwrap_or(location));
+            let metadata = std::fs::metadata(path)
+                .map_err(|e| anyhow!("Installer could not read {}: {}", path.display(), e))?;
+            if !metadata.is_file() {
+                return Err(anyhow!("{} is not a file", path.display()));
+            }
+            let file_name = path
+                .file_name()
+                .ok_or_else(|| anyhow!("{} is not a file", path.display()))?;
+            // an absolute path is read in place, see `InstallSource::local_rootfs`
+            let path = std::fs::canonicalize(path)?;
+
+            (
+                None,
+                file_name.to_string_lossy().to_string(),
+                path.to_string_lossy().to_string(),
+                size.unwrap_or(metadata.len()),
+                metadata.modified()?.into(),
+            )
+        };
+
+    let variant = VariantEntry {
+        name: format!("Custom ({file_name})"),
+        size,
+        install_size: install_size.unwrap_or(size * ROOTFS_EXPANSION_RATIO),
+        date: format!(
+            "{:04}{:02}{:02}",
+            date.year(),
+            u8::from(date.month()),
+            date.day()
+        ),
+        sha256sum,
+        url,
+    };
+
+    Ok((mirror, variant))
+}
+
+#[test]
+fn test_parse_recipe_version() {
+    let recipe = br#"{"version":1,"bulletin":{"type":"none","title":"","title-tr":"","body":"","body-tr":""},"variants":[],"mirrors":[]}"#;
+    assert!(parse_recipe(recipe).is_ok());
+
+    let newer = br#"{"version":2,"something":"else"}"#;
+    assert!(parse_recipe(newer)
+        .unwrap_err()
+        .to_string()
+        .contains("newer than what this installer supports"));
+
+    assert!(parse_recipe(br#"{"variants":[]}"#).is_err());
+}
+
+#[test]
+fn test_latest_variants() {
+    let entry = |name: &str, date: &str| VariantEntry {
+        name: name.to_string(),
+        size: 0,
+        install_size: 0,
+        date: date.to_string(),
+        sha256sum: String::new(),
+        url: String::new(),
+    };
+    let variants = vec![
+        entry("Base", "20240201"),
+        entry("Base", "20240101"),
+        entry("Desktop", "20240115"),
+    ];
+    let latest = latest_variants(&variants);
+
+    assert_eq!(latest.len(), 2);
+    assert_eq!(latest[0].date, "20240201");
+    assert_eq!(latest[1].name, "Desktop");
+}
+
+#[test]
+fn test_network_config() {
+    assert_eq!(NetworkConfig::new(Some("  ")).unwrap().proxy, None);
+    assert_eq!(
+        NetworkConfig::new(Some("socks5h://127.0.0.1:1080"))
+            .unwrap()
+            .proxy
+            .as_deref(),
+        Some("socks5h://127.0.0.1:1080")
+    );
+    assert!(NetworkConfig::new(Some("not a proxy")).is_err());
+    assert!(NetworkConfig::default()
+        .with_ca_certs(vec![PathBuf::from("/nonexistent/ca.pem")], false)
+        .is_err());
+    assert_eq!(
+        NetworkConfig::default()
+            .with_rate_limit(Some(1.5))
+            .unwrap()
+            .rate_limit,
+        Some(1536 * 1024)
+    );
+    assert!(NetworkConfig::default().with_rate_limit(Some(0.0)).is_err());
+}
+
+#[test]
+fn test_verify_recipe() {
+    let key = "RWSeWd0W7AH59tnm9+ruJgfL6+nlK7wKW2ilnd1ixFq56VMFnWMK9ZoU";
+    let signature = "untrusted comment: signature from minisign secret key
+RUSeWd0W7AH59qKEB/Qu9enbfJ2Ln/8M8tT1Ym+4d4b2gVcv9mvPr+8Uot02UUeD3fQuZwLHfWSiwE5XyPAJ1I4WM7HBt4/gcAM=
+trusted comment: timestamp:1700000000\tfile:recipe.json\thashed
+skTD+yScDRqwcg7/c7lvvtUE4NGlzWhmlhWPyeMbTzSQgB0NwcK9k+T/PRR+pnCmeTAcResfffihaU84fOX5AQ==
+";
+    let keys = parse_recipe_keys(&format!("# test key\n\n{key}\n")).unwrap();
+
+    assert!(verify_recipe(br#"{"version":1}"#, signature, &keys).is_ok());
+    assert!(verify_recipe(br#"{"version":2}"#, signature, &keys).is_err());
+    assert!(verify_recipe(br#"{"version":1}"#, signature, &[]).is_err());
+    assert!(parse_recipe_keys("not a key").is_err());
+    // the keys shipped with the installer must all be valid
+    assert!(parse_recipe_keys(RECIPE_PUBLIC_KEYS).is_ok());
+}
+
+#[test]
+fn test_rank_mirrors() {
+    let mirror = |name: &str| Mirror {
+        name: name.to_string(),
+        name_tr: String::new(),
+        loc: String::new(),
+        loc_tr: String::new(),
+        url: String::new(),
+    };
+    let score = |latency: u64, throughput: f64| {
+        Ok(MirrorScore {
+            latency: Duration::from_millis(latency),
+            throughput,
+        })
+    };
+    let mut results = vec![
+        MirrorBenchmark {
+            mirror: mirror("failed"),
+            score: Err("timed out".to_string()),
+        },
+        MirrorBenchmark {
+            mirror: mirror("slow"),
+            score: score(10, 1024.0),
+        },
+        MirrorBenchmark {
+            mirror: mirror("fast-far"),
+            score: score(300, 4096.0),
+        },
+        MirrorBenchmark {
+            mirror: mirror("fast-near"),
+            score: score(20, 4096.0),
+        },
+    ];
+    rank_mirrors(&mut results);
+    let names = results
+        .iter()
+        .map(|x| x.mirror.name.as_str())
+        .collect::<Vec<_>>();
+
+    assert_eq!(names, vec!["fast-near", "fast-far", "slow", "failed"]);
+}
+
+#[test]
+fn test_serialize_mirror_benchmark() {
+    let mirror = Mirror {
+        name: "Example".to_string(),
+        name_tr: "example-name".to_string(),
+        loc: "Earth".to_string(),
+        loc_tr: "example-loc".to_string(),
+        url: "https://example.com/aosc-os/".to_string(),
+    };
+    let result = MirrorBenchmark {
+        mirror: mirror.clone(),
+        score: Ok(MirrorScore {
+            latency: Duration::from_millis(20),
+            throughput: 4096.0,
+        }),
+    };
+    assert_eq!(
+        serde_json::to_value(&result).unwrap(),
+        serde_json::json!({
+            "name": "Example",
+            "name-tr": "example-name",
+            "loc": "Earth",
+            "loc-tr": "example-loc",
+            "url": "https://example.com/aosc-os/",
+            "score": {"latency-ms": 20, "throughput": 4096.0},
+            "error": null,
+        })
+    );
+
+    let result = MirrorBenchmark {
+        mirror,
+        score: Err("timed out".to_string()),
+    };
+    let result = serde_json::to_value(&result).unwrap();
+    assert_eq!(result["score"], serde_json::Value::Null);
+    assert_eq!(result["error"], "timed out");
+}
+
+#[test]
+fn test_custom_rootfs() {
+    let dir = tempfile::tempdir().unwrap();
+    let rootfs = dir.path().join("aosc-os_base_test.squashfs");
+    std::fs::write(&rootfs, b"12345678").unwrap();
+    let sha256sum = "A".repeat(64);
+
+    let (mirror, variant) = custom_rootfs(
+        &rootfs.to_string_lossy(),
+        &sha256sum,
+        None,
+        None,
+        &NetworkConfig::default(),
+    )
+    .unwrap();
+    assert!(mirror.is_none());
+    // the configured recipe source does not matter for a release given by path
+    let source = InstallSource::Remote(MANIFEST_URL.to_string());
+    assert_eq!(
+        source.local_rootfs(&variant),
+        Some(std::fs::canonicalize(&rootfs).unwrap())
+    );
+    assert_eq!(variant.name, "Custom (aosc-os_base_test.squashfs)");
+    assert_eq!(variant.date.len(), 8);
+    assert_eq!(variant.size, 8);
+    assert_eq!(variant.install_size, 8 * ROOTFS_EXPANSION_RATIO);
+    assert_eq!(variant.sha256sum, "a".repeat(64));
+
+    // sizes given on the command line skip the HEAD request
+    let (mirror, variant) = custom_rootfs(
+        "https://example.com/builds/base.tar.xz?token=abc#top",
+        &sha256sum,
+        Some(100),
+        Some(400),
+        &NetworkConfig::default(),
+    )
+    .unwrap();
+    assert_eq!(source.local_rootfs(&variant), None);
+    assert_eq!(variant.name, "Custom (base.tar.xz)");
+    assert_eq!(
+        format!("{}{}", mirror.unwrap().url, variant.url),
+        "https://example.com/builds/base.tar.xz?token=abc"
+    );
+    assert_eq!(variant.install_size, 400);
+
+    assert!(custom_rootfs(
+        &rootfs.to_string_lossy(),
+        "abc",
+        None,
+        None,
+        &NetworkConfig::default()
+    )
+    .is_err());
+}
+
+#[test]
+fn test_bulletin() {
+    let bulletin: Bulletin = serde_json::from_str(
+        r#"{"type":"warning","title":"","title-tr":"bulletin-title","body":"Known issue","body-tr":"bulletin-body"}"#,
+    )
+    .unwrap();
+    assert_eq!(bulletin.title(), "bulletin-title");
+    assert_eq!(bulletin.body(), "Known issue");
+    assert!(!bulletin.is_empty());
+}